@@ -20,6 +20,9 @@ pub struct DeployArgs {
 
     #[clap(long, help = "Path to the module's WASM file")]
     pub wasm_path: String,
+
+    #[clap(long, help = "Name recorded as the deployer of the new version")]
+    pub deployer: Option<String>,
 }
 
 #[derive(Parser)]
@@ -30,6 +33,12 @@ pub struct StartArgs {
 
     #[clap(long, help = "Name of the module to start")]
     pub module_name: String,
+
+    #[clap(
+        long,
+        help = "Block to process history from, instead of resuming where the module left off"
+    )]
+    pub start_block: Option<u64>,
 }
 
 #[derive(Parser)]
@@ -42,6 +51,123 @@ pub struct StopArgs {
     pub module_name: String,
 }
 
+#[derive(Parser)]
+#[clap(about = "Set how a module shares the workers with the others on the xxfunc service")]
+pub struct ScheduleArgs {
+    #[clap(long, help = "URL of the xxfunc service")]
+    pub url: String,
+
+    #[clap(long, help = "Name of the module to schedule")]
+    pub module_name: String,
+
+    #[clap(
+        long,
+        default_value = "Normal",
+        value_parser = ["High", "Normal", "Low"],
+        help = "Priority class, higher classes run first"
+    )]
+    pub priority: String,
+
+    #[clap(
+        long,
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Share of execution time relative to the other modules of the class"
+    )]
+    pub weight: u32,
+}
+
+#[derive(Parser)]
+#[clap(about = "List the versions of a module on the xxfunc service")]
+pub struct VersionsArgs {
+    #[clap(long, help = "URL of the xxfunc service")]
+    pub url: String,
+
+    #[clap(long, help = "Name of the module")]
+    pub module_name: String,
+}
+
+#[derive(Parser)]
+#[clap(about = "Make a module run one of its versions on the xxfunc service")]
+pub struct PromoteArgs {
+    #[clap(long, help = "URL of the xxfunc service")]
+    pub url: String,
+
+    #[clap(long, help = "Name of the module to promote")]
+    pub module_name: String,
+
+    #[clap(long, help = "Version to run")]
+    pub version: u32,
+}
+
+#[derive(Parser)]
+#[clap(about = "Make a module run the version preceding the active one on the xxfunc service")]
+pub struct RollbackArgs {
+    #[clap(long, help = "URL of the xxfunc service")]
+    pub url: String,
+
+    #[clap(long, help = "Name of the module to roll back")]
+    pub module_name: String,
+}
+
+#[derive(Parser)]
+#[clap(about = "List the modules on the xxfunc service")]
+pub struct ListArgs {
+    #[clap(long, help = "URL of the xxfunc service")]
+    pub url: String,
+}
+
+#[derive(Parser)]
+#[clap(about = "Show a module and its metadata on the xxfunc service")]
+pub struct InfoArgs {
+    #[clap(long, help = "URL of the xxfunc service")]
+    pub url: String,
+
+    #[clap(long, help = "Name of the module")]
+    pub module_name: String,
+}
+
+#[derive(Parser)]
+#[clap(about = "Update the metadata of a module on the xxfunc service, keeping what isn't given")]
+pub struct MetadataArgs {
+    #[clap(long, help = "URL of the xxfunc service")]
+    pub url: String,
+
+    #[clap(long, help = "Name of the module")]
+    pub module_name: String,
+
+    #[clap(long, help = "Principal responsible for the module")]
+    pub owner: Option<String>,
+
+    #[clap(long, help = "What the module does")]
+    pub description: Option<String>,
+
+    #[clap(
+        long = "filter",
+        help = "Event the module processes, can be repeated, replaces the filters"
+    )]
+    pub filters: Vec<String>,
+
+    #[clap(long = "tag", help = "Tag of the module, can be repeated, replaces the tags")]
+    pub tags: Vec<String>,
+
+    #[clap(long, help = "Fuel the module needs at most per execution, informational only")]
+    pub fuel: Option<u64>,
+
+    #[clap(long, help = "Memory in bytes the module needs at most, informational only")]
+    pub memory: Option<u64>,
+
+    #[clap(long, help = "Time in milliseconds an execution takes at most, informational only")]
+    pub timeout_ms: Option<u64>,
+
+    #[clap(
+        long = "clear",
+        value_parser = ["owner", "description", "filters", "tags", "fuel", "memory", "timeout_ms"],
+        help = "Field to clear, can be repeated"
+    )]
+    pub clear: Vec<String>,
+}
+
 pub fn build(release: bool) -> eyre::Result<()> {
     let mut args = vec!["wasi", "build"];
     if release {
@@ -71,9 +197,12 @@ pub fn build(release: bool) -> eyre::Result<()> {
     Ok(())
 }
 
-pub fn deploy(url: &str, wasm_file_path: &str) -> eyre::Result<()> {
-    let form =
+pub fn deploy(url: &str, wasm_file_path: &str, deployer: Option<&str>) -> eyre::Result<()> {
+    let mut form =
         multipart::Form::new().file("module", wasm_file_path).expect("Failed to create form file");
+    if let Some(deployer) = deployer {
+        form = form.text("deployer", deployer.to_string());
+    }
 
     let client = reqwest::blocking::Client::new();
     let response = client
@@ -92,12 +221,13 @@ pub fn deploy(url: &str, wasm_file_path: &str) -> eyre::Result<()> {
     }
 }
 
-pub fn start(url: &str, module_name: &str) -> eyre::Result<()> {
+pub fn start(url: &str, module_name: &str, start_block: Option<u64>) -> eyre::Result<()> {
     let client = reqwest::blocking::Client::new();
     let response = client
         .post(&format!("{}/start", url))
         .json(&json!({
-            "module": module_name
+            "module": module_name,
+            "start_block": start_block
         }))
         .send()
         .expect("Failed to send start request");
@@ -130,6 +260,157 @@ pub fn stop(url: &str, module_name: &str) -> eyre::Result<()> {
     }
 }
 
+pub fn schedule(url: &str, module_name: &str, priority: &str, weight: u32) -> eyre::Result<()> {
+    let client = reqwest::blocking::Client::new();
+    let response = client
+        .post(&format!("{}/schedule", url))
+        .json(&json!({
+            "module": module_name,
+            "priority": priority,
+            "weight": weight
+        }))
+        .send()
+        .expect("Failed to send schedule request");
+
+    if response.status().is_success() {
+        println!("xxfunc schedule completed successfully");
+        Ok(())
+    } else {
+        println!("Failed with status: {}", response.status());
+        Err(eyre::eyre!("xxfunc schedule failed"))
+    }
+}
+
+pub fn versions(url: &str, module_name: &str) -> eyre::Result<()> {
+    let client = reqwest::blocking::Client::new();
+    let response = client
+        .post(&format!("{}/versions", url))
+        .json(&json!({
+            "module": module_name
+        }))
+        .send()
+        .expect("Failed to send versions request");
+
+    if response.status().is_success() {
+        println!("{}", response.text()?);
+        Ok(())
+    } else {
+        println!("Failed with status: {}", response.status());
+        Err(eyre::eyre!("xxfunc versions failed"))
+    }
+}
+
+pub fn promote(url: &str, module_name: &str, version: u32) -> eyre::Result<()> {
+    let client = reqwest::blocking::Client::new();
+    let response = client
+        .post(&format!("{}/promote", url))
+        .json(&json!({
+            "module": module_name,
+            "version": version
+        }))
+        .send()
+        .expect("Failed to send promote request");
+
+    if response.status().is_success() {
+        println!("xxfunc promote completed successfully");
+        Ok(())
+    } else {
+        println!("Failed with status: {}", response.status());
+        Err(eyre::eyre!("xxfunc promote failed"))
+    }
+}
+
+pub fn rollback(url: &str, module_name: &str) -> eyre::Result<()> {
+    let client = reqwest::blocking::Client::new();
+    let response = client
+        .post(&format!("{}/rollback", url))
+        .json(&json!({
+            "module": module_name
+        }))
+        .send()
+        .expect("Failed to send rollback request");
+
+    if response.status().is_success() {
+        println!("xxfunc rollback completed successfully");
+        println!("Response: {}", response.text()?);
+        Ok(())
+    } else {
+        println!("Failed with status: {}", response.status());
+        Err(eyre::eyre!("xxfunc rollback failed"))
+    }
+}
+
+pub fn list(url: &str) -> eyre::Result<()> {
+    let client = reqwest::blocking::Client::new();
+    let response =
+        client.get(&format!("{}/list", url)).send().expect("Failed to send list request");
+
+    if response.status().is_success() {
+        println!("{}", response.text()?);
+        Ok(())
+    } else {
+        println!("Failed with status: {}", response.status());
+        Err(eyre::eyre!("xxfunc list failed"))
+    }
+}
+
+pub fn info(url: &str, module_name: &str) -> eyre::Result<()> {
+    let client = reqwest::blocking::Client::new();
+    let response = client
+        .post(&format!("{}/info", url))
+        .json(&json!({
+            "module": module_name
+        }))
+        .send()
+        .expect("Failed to send info request");
+
+    if response.status().is_success() {
+        println!("{}", response.text()?);
+        Ok(())
+    } else {
+        println!("Failed with status: {}", response.status());
+        Err(eyre::eyre!("xxfunc info failed"))
+    }
+}
+
+pub fn metadata(args: &MetadataArgs) -> eyre::Result<()> {
+    // fields left out are kept and null ones cleared
+    let mut body = json!({ "module": args.module_name });
+    let fields = body.as_object_mut().expect("body is an object");
+    for field in &args.clear {
+        fields.insert(field.clone(), serde_json::Value::Null);
+    }
+    let given = [
+        ("owner", args.owner.as_ref().map(|owner| json!(owner))),
+        ("description", args.description.as_ref().map(|description| json!(description))),
+        ("filters", (!args.filters.is_empty()).then(|| json!(args.filters))),
+        ("tags", (!args.tags.is_empty()).then(|| json!(args.tags))),
+        ("fuel", args.fuel.map(|fuel| json!(fuel))),
+        ("memory", args.memory.map(|memory| json!(memory))),
+        ("timeout_ms", args.timeout_ms.map(|timeout_ms| json!(timeout_ms))),
+    ];
+    for (field, value) in given {
+        if let Some(value) = value {
+            fields.insert(field.to_string(), value);
+        }
+    }
+
+    let client = reqwest::blocking::Client::new();
+    let response = client
+        .post(&format!("{}/metadata", args.url))
+        .json(&body)
+        .send()
+        .expect("Failed to send metadata request");
+
+    if response.status().is_success() {
+        println!("xxfunc metadata completed successfully");
+        Ok(())
+    } else {
+        println!("Failed with status: {}", response.status());
+        Err(eyre::eyre!("xxfunc metadata failed"))
+    }
+}
+
 // Helper function to get the project name from Cargo.toml
 fn get_project_name() -> eyre::Result<String> {
     let cargo_toml: Value = toml::from_str(&fs::read_to_string("Cargo.toml")?)?;
@@ -1,4 +1,8 @@
-use args::{build, deploy, start, stop, BuildArgs, DeployArgs, StartArgs, StopArgs};
+use args::{
+    build, deploy, info, list, metadata, promote, rollback, schedule, start, stop, versions,
+    BuildArgs, DeployArgs, InfoArgs, ListArgs, MetadataArgs, PromoteArgs, RollbackArgs,
+    ScheduleArgs, StartArgs, StopArgs, VersionsArgs,
+};
 use clap::{Parser, Subcommand};
 
 mod args;
@@ -16,6 +20,13 @@ enum Command {
     Deploy(DeployArgs),
     Start(StartArgs),
     Stop(StopArgs),
+    Schedule(ScheduleArgs),
+    Versions(VersionsArgs),
+    Promote(PromoteArgs),
+    Rollback(RollbackArgs),
+    List(ListArgs),
+    Info(InfoArgs),
+    Metadata(MetadataArgs),
 }
 
 fn main() -> eyre::Result<()> {
@@ -23,8 +34,30 @@ fn main() -> eyre::Result<()> {
 
     match args.command {
         Command::Build(build_args) => build(build_args.release),
-        Command::Deploy(deploy_args) => deploy(&deploy_args.url, &deploy_args.wasm_path),
-        Command::Start(start_args) => start(&start_args.url, &start_args.module_name),
+        Command::Deploy(deploy_args) => {
+            deploy(&deploy_args.url, &deploy_args.wasm_path, deploy_args.deployer.as_deref())
+        }
+        Command::Start(start_args) => {
+            start(&start_args.url, &start_args.module_name, start_args.start_block)
+        }
         Command::Stop(stop_args) => stop(&stop_args.url, &stop_args.module_name),
+        Command::Schedule(schedule_args) => schedule(
+            &schedule_args.url,
+            &schedule_args.module_name,
+            &schedule_args.priority,
+            schedule_args.weight,
+        ),
+        Command::Versions(versions_args) => {
+            versions(&versions_args.url, &versions_args.module_name)
+        }
+        Command::Promote(promote_args) => {
+            promote(&promote_args.url, &promote_args.module_name, promote_args.version)
+        }
+        Command::Rollback(rollback_args) => {
+            rollback(&rollback_args.url, &rollback_args.module_name)
+        }
+        Command::List(list_args) => list(&list_args.url),
+        Command::Info(info_args) => info(&info_args.url, &info_args.module_name),
+        Command::Metadata(metadata_args) => metadata(&metadata_args),
     }
 }
@@ -1,136 +1,375 @@
 use eyre::Result;
-use r2d2::Pool;
-use r2d2_sqlite::SqliteConnectionManager;
-use rusqlite::{params, OpenFlags};
-use std::{fs::OpenOptions, path::Path};
+use sha2::{Digest, Sha256};
+use std::{
+    fmt,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+pub mod memory;
+pub mod migrations;
+pub mod sqlite;
+
+pub use memory::MemoryStore;
+pub use sqlite::{ModuleDatabase, BUSY_TIMEOUT};
 
 pub type ModuleId = i64;
 
 // Enum to represent module states
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ModuleState {
     Started,
     Stopped,
 }
 
-// database for storing and retrieving wasm modules
-#[derive(Debug, Clone)]
-pub struct ModuleDatabase {
-    pool: Pool<SqliteConnectionManager>,
-}
-
-impl ModuleDatabase {
-    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let _ =
-            OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&path)?;
-
-        let manager =
-            SqliteConnectionManager::file(path).with_flags(OpenFlags::SQLITE_OPEN_READ_WRITE);
-        let pool = Pool::new(manager)?;
-
-        let conn = pool.get()?;
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS modules (
-                id INTEGER PRIMARY KEY,
-                name TEXT NOT NULL UNIQUE,
-                binary BLOB NOT NULL
-            )",
-            [],
-        )?;
-
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS module_states (
-                module_id INTEGER PRIMARY KEY,
-                state TEXT NOT NULL,
-                FOREIGN KEY(module_id) REFERENCES modules(id)
-            )",
-            [],
-        )?;
-
-        Ok(ModuleDatabase { pool })
-    }
-
-    pub fn insert(&self, name: &str, binary: &[u8]) -> Result<()> {
-        let conn = self.pool.get().unwrap();
-        conn.execute("INSERT INTO modules (name, binary) VALUES (?1, ?2)", params![name, binary])?;
-        let module_id = conn.last_insert_rowid();
-        conn.execute(
-            "INSERT INTO module_states (module_id, state) VALUES (?1, ?2)",
-            params![module_id, ModuleState::Stopped.to_string()],
-        )?;
-        Ok(())
+/// Scheduling class of a module. Queued tasks of a higher class always run first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    High,
+    #[default]
+    Normal,
+    Low,
+}
+
+/// How the runtime shares workers between a module and the others of its priority class.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModuleSchedule {
+    pub priority: Priority,
+    /// share of execution time relative to the other modules of the class, at least 1
+    pub weight: u32,
+}
+
+impl Default for ModuleSchedule {
+    fn default() -> Self {
+        Self { priority: Priority::default(), weight: 1 }
     }
+}
 
-    pub fn get(&self, id: ModuleId) -> Result<Option<Vec<u8>>> {
-        let conn = self.pool.get()?;
+/// Progress of a module through the chain.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ModuleCheckpoint {
+    /// block to process history from when the module has no progress yet
+    pub start_block: Option<u64>,
+    /// last block the module processed
+    pub last_block: Option<u64>,
+}
+
+impl ModuleCheckpoint {
+    /// First block the module hasn't processed, `None` if it only follows new blocks.
+    pub fn next_block(&self) -> Option<u64> {
+        self.last_block.map(|block| block + 1).or(self.start_block)
+    }
+}
 
-        let mut stmt = conn.prepare("SELECT binary FROM modules WHERE id = ?1")?;
-        let mut rows = stmt.query(rusqlite::params![id])?;
+/// Resources a module declares needing at most for one execution, `None` if it doesn't say.
+/// Informational only: executions are bounded by the limits the runtime is configured with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResourceLimits {
+    pub fuel: Option<u64>,
+    /// linear memory in bytes
+    pub memory: Option<u64>,
+    pub timeout: Option<Duration>,
+}
+
+/// Details of a module set by its owner, independent of the deployed versions.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ModuleMetadata {
+    /// principal responsible for the module, the first deployer unless set
+    pub owner: Option<String>,
+    pub description: Option<String>,
+    /// events the module processes, e.g. contract addresses or log topics
+    pub filters: Vec<String>,
+    pub limits: ResourceLimits,
+    pub tags: Vec<String>,
+}
 
-        if let Some(row) = rows.next()? {
-            let binary: Vec<u8> = row.get(0)?;
-            Ok(Some(binary))
-        } else {
-            Ok(None)
+/// Change of the metadata of a module, see [`ModuleStore::update_metadata`]. `None` keeps a
+/// field and `Some(None)` clears it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MetadataUpdate {
+    pub owner: Option<Option<String>>,
+    pub description: Option<Option<String>>,
+    /// replaces the filters, cleared by an empty list
+    pub filters: Option<Vec<String>>,
+    pub fuel: Option<Option<u64>>,
+    pub memory: Option<Option<u64>>,
+    pub timeout: Option<Option<Duration>>,
+    /// replaces the tags, cleared by an empty list
+    pub tags: Option<Vec<String>>,
+}
+
+impl MetadataUpdate {
+    /// Changes the given fields of the metadata.
+    pub fn apply(self, metadata: &mut ModuleMetadata) {
+        if let Some(owner) = self.owner {
+            metadata.owner = owner;
+        }
+        if let Some(description) = self.description {
+            metadata.description = description;
+        }
+        if let Some(filters) = self.filters {
+            metadata.filters = filters;
+        }
+        if let Some(fuel) = self.fuel {
+            metadata.limits.fuel = fuel;
+        }
+        if let Some(memory) = self.memory {
+            metadata.limits.memory = memory;
+        }
+        if let Some(timeout) = self.timeout {
+            metadata.limits.timeout = timeout;
+        }
+        if let Some(tags) = self.tags {
+            metadata.tags = tags;
         }
     }
+}
 
-    pub fn delete(&self, name: &str) -> Result<()> {
-        let mut conn = self.pool.get()?;
-        let tx = conn.transaction()?;
+impl From<ModuleMetadata> for MetadataUpdate {
+    // replaces every field
+    fn from(metadata: ModuleMetadata) -> Self {
+        Self {
+            owner: Some(metadata.owner),
+            description: Some(metadata.description),
+            filters: Some(metadata.filters),
+            fuel: Some(metadata.limits.fuel),
+            memory: Some(metadata.limits.memory),
+            timeout: Some(metadata.limits.timeout),
+            tags: Some(metadata.tags),
+        }
+    }
+}
 
-        let module_id: i64 =
-            tx.query_row("SELECT id FROM modules WHERE name = ?1", params![name], |row| {
-                row.get(0)
-            })?;
+/// Summary of a deployed module.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModuleInfo {
+    pub id: ModuleId,
+    pub name: String,
+    pub state: ModuleState,
+    /// active version, the one the module runs
+    pub version: u32,
+    /// size of the wasm binary in bytes
+    pub size: usize,
+    /// hex encoded SHA-256 of the wasm binary
+    pub hash: String,
+    /// ABI version the active binary was validated against, if known
+    pub abi_version: Option<u32>,
+    /// unix timestamp in seconds of the first deploy
+    pub created_at: u64,
+    /// unix timestamp in seconds of the last deploy, promotion, rollback or metadata change
+    pub updated_at: u64,
+    pub metadata: ModuleMetadata,
+}
 
-        tx.execute("DELETE FROM module_states WHERE module_id = ?1", params![module_id])?;
-        tx.execute("DELETE FROM modules WHERE id = ?1", params![module_id])?;
+/// Binary deployed under a module name. Versions are never modified, deploying again adds a new
+/// one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModuleVersion {
+    /// number of the version, starting at 1 and increasing with every deploy of the module
+    pub version: u32,
+    /// hex encoded SHA-256 of the wasm binary
+    pub hash: String,
+    /// size of the wasm binary in bytes
+    pub size: usize,
+    /// unix timestamp in seconds
+    pub deployed_at: u64,
+    /// who deployed the version, if known
+    pub deployer: Option<String>,
+    /// ABI version the binary was validated against, if known
+    pub abi_version: Option<u32>,
+    /// whether the module runs this version
+    pub active: bool,
+}
 
-        tx.commit()?;
-        Ok(())
-    }
+/// Binary of a module as stored, along with the hash it is stored under.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModuleBinary {
+    /// version of the module the binary was deployed as
+    pub version: u32,
+    /// hex encoded SHA-256 of the wasm binary when it was deployed
+    pub hash: String,
+    pub binary: Vec<u8>,
+}
 
-    pub fn set_state(&self, name: &str, state: ModuleState) -> Result<()> {
-        let conn = self.pool.get().unwrap();
-        let rows_affected = conn.execute(
-            "UPDATE module_states
-             SET state = ?1
-             WHERE module_id = (SELECT id FROM modules WHERE name = ?2)",
-            params![state.to_string(), name],
-        )?;
-        if rows_affected == 0 {
-            return Err(eyre::eyre!("Module not found"));
+impl ModuleBinary {
+    /// Fails if the binary no longer matches its hash, e.g. after the database got corrupted.
+    pub fn verify(&self) -> Result<()> {
+        let hash = hex_hash(&self.binary);
+        if hash != self.hash {
+            return Err(eyre::eyre!(
+                "Module binary corrupted: expected hash {}, found {hash}",
+                self.hash
+            ));
         }
         Ok(())
     }
+}
 
-    pub fn get_modules_by_state(&self, state: ModuleState) -> Result<Vec<ModuleId>> {
-        let conn = self.pool.get()?;
-        let mut stmt = conn.prepare(
-            "SELECT module_id
-             FROM module_states
-             WHERE state = ?1",
-        )?;
+/// Failure of a [`ModuleStore`] operation.
+#[derive(Debug)]
+pub enum StoreError {
+    /// No module has the given name.
+    NotFound,
+    /// The module has no version with the given number.
+    VersionNotFound,
+    /// The active version of the module is its first one.
+    NoEarlierVersion,
+    /// The schedule gives the module a weight of 0.
+    InvalidWeight,
+    /// The backend failed, e.g. the database couldn't be reached or holds invalid data.
+    Backend(eyre::Report),
+}
 
-        let rows = stmt.query_map(params![state.to_string()], |row| row.get(0))?;
-        let mut modules = Vec::new();
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::NotFound => write!(f, "Module not found"),
+            StoreError::VersionNotFound => write!(f, "Module version not found"),
+            StoreError::NoEarlierVersion => write!(f, "No earlier version to roll back to"),
+            StoreError::InvalidWeight => write!(f, "Module weight must be at least 1"),
+            StoreError::Backend(err) => write!(f, "Module store failed: {err}"),
+        }
+    }
+}
 
-        for id in rows {
-            modules.push(id?);
+impl std::error::Error for StoreError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StoreError::Backend(err) => Some(err.as_ref()),
+            _ => None,
         }
+    }
+}
+
+impl From<eyre::Report> for StoreError {
+    fn from(err: eyre::Report) -> Self {
+        StoreError::Backend(err)
+    }
+}
+
+pub type StoreResult<T> = std::result::Result<T, StoreError>;
+
+/// Storage of modules, their versions and settings, shared by the runtime, the scheduler and
+/// the server.
+///
+/// Changes made through a store are visible to every handle to it, including ones in other
+/// processes for stores backed by a database server or file. Methods taking a name fail with
+/// [`StoreError::NotFound`] if no module has it, unless they return an `Option`.
+///
+/// Methods block on the backend, e.g. on disk or network I/O, so async code calls them through
+/// [`call_blocking`].
+pub trait ModuleStore: Send + Sync {
+    /// Counter increased by every change to the modules, their states or schedules, from any
+    /// process. Comparing it to a previous value tells whether modules need to be reloaded.
+    /// Checkpoints don't count as changes.
+    fn generation(&self) -> StoreResult<u64>;
+
+    /// Deploys the binary as a new version of the module, created if missing, and makes it the
+    /// active one. A new module is stopped.
+    ///
+    /// Binaries are stored by hash, so deploying one already stored, under any module name,
+    /// doesn't store it again. The deployer of a new module becomes its owner.
+    fn deploy(
+        &self,
+        name: &str,
+        binary: &[u8],
+        deployer: Option<&str>,
+        abi_version: Option<u32>,
+    ) -> StoreResult<ModuleVersion>;
+
+    /// Deploys the binary without a known deployer or ABI version, see
+    /// [`ModuleStore::deploy`].
+    fn insert(&self, name: &str, binary: &[u8]) -> StoreResult<()> {
+        self.deploy(name, binary, None, None)?;
+        Ok(())
+    }
 
-        Ok(modules)
+    /// Binary of the active version of the module.
+    fn get(&self, id: ModuleId) -> StoreResult<Option<Vec<u8>>> {
+        Ok(self.get_binary(id)?.map(|binary| binary.binary))
     }
 
-    // Function to create a test database and insert a WASM binary file
-    pub fn create_test_db() -> Result<Self> {
-        let temp_file = tempfile::NamedTempFile::new()?.into_temp_path().with_extension("db");
-        let db = ModuleDatabase::open(&temp_file)?;
-        let bytes = include_bytes!("../../examples/minimal/wasm_output/output.wasm");
-        db.insert("test_module", bytes)?;
-        Ok(db)
+    /// Binary of the active version of the module along with its number and hash, to
+    /// [verify](ModuleBinary::verify) it before running it.
+    fn get_binary(&self, id: ModuleId) -> StoreResult<Option<ModuleBinary>>;
+
+    /// Active version of the module, `None` if it doesn't exist.
+    fn get_active_version(&self, id: ModuleId) -> StoreResult<Option<u32>>;
+
+    /// Every version of the module, oldest first.
+    fn list_versions(&self, name: &str) -> StoreResult<Vec<ModuleVersion>>;
+
+    /// Makes the module run the given version from its next execution on. Tasks already queued
+    /// run the new version as well. Fails with [`StoreError::VersionNotFound`] if the module has
+    /// no such version.
+    fn promote(&self, name: &str, version: u32) -> StoreResult<()>;
+
+    /// Promotes the version preceding the active one, returning it. Fails with
+    /// [`StoreError::NoEarlierVersion`] if the first version is the active one.
+    fn rollback(&self, name: &str) -> StoreResult<u32>;
+
+    /// Id of the module with the given name, `None` if it doesn't exist.
+    fn get_id(&self, name: &str) -> StoreResult<Option<ModuleId>>;
+
+    /// Deletes the module with all its versions, and the binaries no other module deployed.
+    fn delete(&self, name: &str) -> StoreResult<()>;
+
+    /// Replaces the metadata of the module.
+    fn set_metadata(&self, name: &str, metadata: &ModuleMetadata) -> StoreResult<()> {
+        self.update_metadata(name, metadata.clone().into())
     }
+
+    /// Changes the given fields of the metadata of the module at once, keeping the others even
+    /// if they are changed concurrently.
+    fn update_metadata(&self, name: &str, update: MetadataUpdate) -> StoreResult<()>;
+
+    fn set_state(&self, name: &str, state: ModuleState) -> StoreResult<()>;
+
+    /// Fails with [`StoreError::InvalidWeight`] if the weight is 0.
+    fn set_schedule(&self, name: &str, schedule: ModuleSchedule) -> StoreResult<()>;
+
+    /// Scheduling settings of the module, `None` if it doesn't exist.
+    fn get_schedule(&self, id: ModuleId) -> StoreResult<Option<ModuleSchedule>>;
+
+    /// Makes the module process history from `start_block` when it is next started, dropping
+    /// its progress. `None` makes it only follow new blocks.
+    fn set_start_block(&self, name: &str, start_block: Option<u64>) -> StoreResult<()>;
+
+    /// Records the last block processed by the module. Fails with [`StoreError::NotFound`] if
+    /// the module doesn't exist, e.g. it was deleted meanwhile.
+    fn set_last_block(&self, id: ModuleId, block: u64) -> StoreResult<()>;
+
+    fn get_checkpoint(&self, id: ModuleId) -> StoreResult<ModuleCheckpoint>;
+
+    fn get_state(&self, id: ModuleId) -> StoreResult<Option<ModuleState>>;
+
+    /// Every deployed module, ordered by id.
+    fn list(&self) -> StoreResult<Vec<ModuleInfo>>;
+
+    /// Details of the module, `None` if it doesn't exist.
+    fn get_info(&self, name: &str) -> StoreResult<Option<ModuleInfo>>;
+
+    fn get_modules_by_state(&self, state: ModuleState) -> StoreResult<Vec<ModuleId>>;
+}
+
+/// Runs the call on a thread where blocking is allowed, for async code to use the store.
+pub async fn call_blocking<T: Send + 'static>(
+    store: &Arc<dyn ModuleStore>,
+    call: impl FnOnce(&dyn ModuleStore) -> StoreResult<T> + Send + 'static,
+) -> StoreResult<T> {
+    let store = Arc::clone(store);
+    tokio::task::spawn_blocking(move || call(&*store))
+        .await
+        .map_err(|err| StoreError::Backend(err.into()))?
+}
+
+pub(crate) fn unix_timestamp() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |now| now.as_secs())
+}
+
+// hex encoded SHA-256 of the binary
+pub(crate) fn hex_hash(binary: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(binary))
 }
 
 impl std::fmt::Display for ModuleState {
@@ -153,3 +392,147 @@ impl std::str::FromStr for ModuleState {
         }
     }
 }
+
+impl std::fmt::Display for Priority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Priority::High => write!(f, "High"),
+            Priority::Normal => write!(f, "Normal"),
+            Priority::Low => write!(f, "Low"),
+        }
+    }
+}
+
+impl std::str::FromStr for Priority {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "High" => Ok(Priority::High),
+            "Normal" => Ok(Priority::Normal),
+            "Low" => Ok(Priority::Low),
+            _ => Err(eyre::eyre!("Invalid module priority")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_versions() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        check_versions(&ModuleDatabase::open(dir.path().join("module.db"))?)?;
+        check_versions(&MemoryStore::new())
+    }
+
+    #[test]
+    fn test_metadata() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        check_metadata(&ModuleDatabase::open(dir.path().join("module.db"))?)?;
+        check_metadata(&MemoryStore::new())
+    }
+
+    fn check_versions(db: &dyn ModuleStore) -> Result<()> {
+        let first = db.deploy("module", b"first", Some("alice"), Some(1))?;
+        assert_eq!(first.version, 1);
+        assert_eq!(first.hash, hex_hash(b"first"));
+        let id = db.get_id("module")?.expect("module exists");
+        db.set_state("module", ModuleState::Started)?;
+
+        // redeploying keeps the module, its state and its id
+        let second = db.deploy("module", b"second", None, None)?;
+        assert_eq!(second.version, 2);
+        assert_eq!(db.get_id("module")?, Some(id));
+        assert_eq!(db.get_state(id)?, Some(ModuleState::Started));
+        assert_eq!(db.get(id)?, Some(b"second".to_vec()));
+
+        let versions = db.list_versions("module")?;
+        assert_eq!(versions, vec![ModuleVersion { active: false, ..first.clone() }, second]);
+        assert_eq!(versions[0].deployer.as_deref(), Some("alice"));
+
+        assert_eq!(db.rollback("module")?, 1);
+        assert_eq!(db.get_active_version(id)?, Some(1));
+        assert_eq!(db.get(id)?, Some(b"first".to_vec()));
+        assert!(matches!(db.rollback("module"), Err(StoreError::NoEarlierVersion)));
+
+        db.promote("module", 2)?;
+        assert_eq!(db.get(id)?, Some(b"second".to_vec()));
+        assert!(matches!(db.promote("module", 3), Err(StoreError::VersionNotFound)));
+        assert!(matches!(db.promote("missing", 1), Err(StoreError::NotFound)));
+
+        let modules = db.list()?;
+        assert_eq!(modules.len(), 1);
+        assert_eq!((modules[0].version, modules[0].size), (2, b"second".len()));
+
+        db.delete("module")?;
+        assert!(matches!(db.list_versions("module"), Err(StoreError::NotFound)));
+        assert!(matches!(db.delete("module"), Err(StoreError::NotFound)));
+
+        Ok(())
+    }
+
+    fn check_metadata(db: &dyn ModuleStore) -> Result<()> {
+        assert_eq!(db.get_info("module")?, None);
+
+        db.deploy("module", b"binary", Some("alice"), Some(1))?;
+        let info = db.get_info("module")?.expect("module exists");
+        assert_eq!(info.metadata.owner.as_deref(), Some("alice"));
+        assert_eq!((info.size, info.abi_version), (b"binary".len(), Some(1)));
+        assert!(info.created_at > 0 && info.updated_at >= info.created_at);
+
+        let metadata = ModuleMetadata {
+            owner: Some("bob".to_string()),
+            description: Some("indexes transfers".to_string()),
+            filters: vec!["0xa0b8".to_string()],
+            limits: ResourceLimits {
+                fuel: Some(1_000_000),
+                memory: Some(1 << 20),
+                timeout: Some(Duration::from_millis(500)),
+            },
+            tags: vec!["erc20".to_string(), "defi".to_string()],
+        };
+        db.set_metadata("module", &metadata)?;
+        assert!(matches!(db.set_metadata("missing", &metadata), Err(StoreError::NotFound)));
+
+        // fields left out are kept, null ones cleared
+        let update = MetadataUpdate {
+            description: Some(None),
+            timeout: Some(None),
+            tags: Some(vec!["erc20".to_string(), "defi".to_string(), "defi".to_string()]),
+            ..Default::default()
+        };
+        db.update_metadata("module", update.clone())?;
+        assert!(matches!(db.update_metadata("missing", update), Err(StoreError::NotFound)));
+        let cleared = ModuleMetadata {
+            description: None,
+            limits: ResourceLimits { timeout: None, ..metadata.limits },
+            ..metadata.clone()
+        };
+        assert_eq!(
+            db.get_info("module")?.expect("module exists").metadata,
+            ModuleMetadata { tags: vec!["defi".to_string(), "erc20".to_string()], ..cleared }
+        );
+        db.set_metadata("module", &metadata)?;
+
+        // a new version keeps the metadata of the module
+        db.deploy("module", b"new", Some("carol"), None)?;
+        db.insert("other", b"other")?;
+        let modules = db.list()?;
+        assert_eq!(modules.len(), 2);
+        assert_eq!(modules[0].version, 2);
+        assert_eq!(modules[0].abi_version, None);
+        assert_eq!(
+            modules[0].metadata,
+            ModuleMetadata { tags: vec!["defi".to_string(), "erc20".to_string()], ..metadata }
+        );
+        assert_eq!(modules[1].metadata, ModuleMetadata::default());
+        assert_eq!(db.get_info("other")?.as_ref(), modules.get(1));
+
+        db.delete("module")?;
+        assert_eq!(db.get_info("module")?, None);
+
+        Ok(())
+    }
+}
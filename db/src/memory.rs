@@ -0,0 +1,357 @@
+//! [`ModuleStore`] keeping everything in memory, for tests and the dev node.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Mutex, MutexGuard},
+};
+
+use crate::{
+    hex_hash, unix_timestamp, MetadataUpdate, ModuleBinary, ModuleCheckpoint, ModuleId, ModuleInfo,
+    ModuleMetadata, ModuleSchedule, ModuleState, ModuleStore, ModuleVersion, StoreError,
+    StoreResult,
+};
+
+/// Store lost once dropped. Clones of the [`Arc`](std::sync::Arc) it is usually shared in see
+/// the same modules.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    generation: u64,
+    /// id of the last module created
+    last_id: ModuleId,
+    modules: BTreeMap<ModuleId, Module>,
+    /// binaries by hex encoded hash
+    binaries: HashMap<String, Vec<u8>>,
+}
+
+#[derive(Debug)]
+struct Module {
+    name: String,
+    state: ModuleState,
+    schedule: ModuleSchedule,
+    checkpoint: ModuleCheckpoint,
+    active_version: u32,
+    /// versions by number, starting at 1 without gaps
+    versions: Vec<Version>,
+    metadata: ModuleMetadata,
+    created_at: u64,
+    updated_at: u64,
+}
+
+#[derive(Debug)]
+struct Version {
+    hash: String,
+    deployed_at: u64,
+    deployer: Option<String>,
+    abi_version: Option<u32>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        // the data is left consistent by every method, even one that panicked
+        self.inner.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Inner {
+    fn find(&self, name: &str) -> Option<(ModuleId, &Module)> {
+        self.modules.iter().find(|(_, module)| module.name == name).map(|(id, m)| (*id, m))
+    }
+
+    fn find_mut(&mut self, name: &str) -> StoreResult<&mut Module> {
+        self.modules.values_mut().find(|module| module.name == name).ok_or(StoreError::NotFound)
+    }
+
+    fn info(&self, id: ModuleId, module: &Module) -> ModuleInfo {
+        let version = module.active();
+        ModuleInfo {
+            id,
+            name: module.name.clone(),
+            state: module.state,
+            version: module.active_version,
+            size: self.binaries[&version.hash].len(),
+            hash: version.hash.clone(),
+            abi_version: version.abi_version,
+            created_at: module.created_at,
+            updated_at: module.updated_at,
+            metadata: module.metadata.clone(),
+        }
+    }
+}
+
+impl Module {
+    fn active(&self) -> &Version {
+        &self.versions[self.active_version as usize - 1]
+    }
+}
+
+impl ModuleStore for MemoryStore {
+    fn generation(&self) -> StoreResult<u64> {
+        Ok(self.lock().generation)
+    }
+
+    fn deploy(
+        &self,
+        name: &str,
+        binary: &[u8],
+        deployer: Option<&str>,
+        abi_version: Option<u32>,
+    ) -> StoreResult<ModuleVersion> {
+        let hash = hex_hash(binary);
+        let deployed_at = unix_timestamp();
+        let version = Version {
+            hash: hash.clone(),
+            deployed_at,
+            deployer: deployer.map(str::to_string),
+            abi_version,
+        };
+
+        let mut inner = self.lock();
+        inner.binaries.entry(hash.clone()).or_insert_with(|| binary.to_vec());
+        let number = match inner.find_mut(name) {
+            Ok(module) => {
+                module.versions.push(version);
+                module.active_version = module.versions.len() as u32;
+                module.updated_at = deployed_at;
+                module.active_version
+            }
+            Err(_) => {
+                inner.last_id += 1;
+                let id = inner.last_id;
+                let module = Module {
+                    name: name.to_string(),
+                    state: ModuleState::Stopped,
+                    schedule: ModuleSchedule::default(),
+                    checkpoint: ModuleCheckpoint::default(),
+                    active_version: 1,
+                    versions: vec![version],
+                    metadata: ModuleMetadata {
+                        owner: deployer.map(str::to_string),
+                        ..Default::default()
+                    },
+                    created_at: deployed_at,
+                    updated_at: deployed_at,
+                };
+                inner.modules.insert(id, module);
+                1
+            }
+        };
+        inner.generation += 1;
+
+        Ok(ModuleVersion {
+            version: number,
+            hash,
+            size: binary.len(),
+            deployed_at,
+            deployer: deployer.map(str::to_string),
+            abi_version,
+            active: true,
+        })
+    }
+
+    fn get_binary(&self, id: ModuleId) -> StoreResult<Option<ModuleBinary>> {
+        let inner = self.lock();
+        Ok(inner.modules.get(&id).map(|module| {
+            let hash = module.active().hash.clone();
+            ModuleBinary {
+                version: module.active_version,
+                binary: inner.binaries[&hash].clone(),
+                hash,
+            }
+        }))
+    }
+
+    fn get_active_version(&self, id: ModuleId) -> StoreResult<Option<u32>> {
+        Ok(self.lock().modules.get(&id).map(|module| module.active_version))
+    }
+
+    fn list_versions(&self, name: &str) -> StoreResult<Vec<ModuleVersion>> {
+        let inner = self.lock();
+        let (_, module) = inner.find(name).ok_or(StoreError::NotFound)?;
+        Ok(module
+            .versions
+            .iter()
+            .zip(1..)
+            .map(|(version, number)| ModuleVersion {
+                version: number,
+                hash: version.hash.clone(),
+                size: inner.binaries[&version.hash].len(),
+                deployed_at: version.deployed_at,
+                deployer: version.deployer.clone(),
+                abi_version: version.abi_version,
+                active: number == module.active_version,
+            })
+            .collect())
+    }
+
+    fn promote(&self, name: &str, version: u32) -> StoreResult<()> {
+        let mut inner = self.lock();
+        let module = inner.find_mut(name)?;
+        if !(1..=module.versions.len() as u32).contains(&version) {
+            return Err(StoreError::VersionNotFound);
+        }
+        module.active_version = version;
+        module.updated_at = unix_timestamp();
+        inner.generation += 1;
+        Ok(())
+    }
+
+    fn rollback(&self, name: &str) -> StoreResult<u32> {
+        let mut inner = self.lock();
+        let module = inner.find_mut(name)?;
+        if module.active_version == 1 {
+            return Err(StoreError::NoEarlierVersion);
+        }
+        module.active_version -= 1;
+        module.updated_at = unix_timestamp();
+        let version = module.active_version;
+        inner.generation += 1;
+        Ok(version)
+    }
+
+    fn get_id(&self, name: &str) -> StoreResult<Option<ModuleId>> {
+        Ok(self.lock().find(name).map(|(id, _)| id))
+    }
+
+    fn delete(&self, name: &str) -> StoreResult<()> {
+        let mut inner = self.lock();
+        let (id, _) = inner.find(name).ok_or(StoreError::NotFound)?;
+        inner.modules.remove(&id);
+
+        // binaries no other module deployed
+        let Inner { modules, binaries, .. } = &mut *inner;
+        binaries.retain(|hash, _| {
+            modules
+                .values()
+                .flat_map(|module| &module.versions)
+                .any(|version| version.hash == *hash)
+        });
+        inner.generation += 1;
+        Ok(())
+    }
+
+    fn update_metadata(&self, name: &str, update: MetadataUpdate) -> StoreResult<()> {
+        let mut inner = self.lock();
+        let module = inner.find_mut(name)?;
+        let metadata = &mut module.metadata;
+        update.apply(metadata);
+        // listed sorted and once each, like the tables of the SQLite store
+        metadata.tags.sort();
+        metadata.tags.dedup();
+        metadata.filters.sort();
+        metadata.filters.dedup();
+        module.updated_at = unix_timestamp();
+        inner.generation += 1;
+        Ok(())
+    }
+
+    fn set_state(&self, name: &str, state: ModuleState) -> StoreResult<()> {
+        let mut inner = self.lock();
+        inner.find_mut(name)?.state = state;
+        inner.generation += 1;
+        Ok(())
+    }
+
+    fn set_schedule(&self, name: &str, schedule: ModuleSchedule) -> StoreResult<()> {
+        if schedule.weight == 0 {
+            return Err(StoreError::InvalidWeight);
+        }
+
+        let mut inner = self.lock();
+        inner.find_mut(name)?.schedule = schedule;
+        inner.generation += 1;
+        Ok(())
+    }
+
+    fn get_schedule(&self, id: ModuleId) -> StoreResult<Option<ModuleSchedule>> {
+        Ok(self.lock().modules.get(&id).map(|module| module.schedule))
+    }
+
+    fn set_start_block(&self, name: &str, start_block: Option<u64>) -> StoreResult<()> {
+        self.lock().find_mut(name)?.checkpoint = ModuleCheckpoint { start_block, last_block: None };
+        Ok(())
+    }
+
+    fn set_last_block(&self, id: ModuleId, block: u64) -> StoreResult<()> {
+        let mut inner = self.lock();
+        let module = inner.modules.get_mut(&id).ok_or(StoreError::NotFound)?;
+        module.checkpoint.last_block = Some(block);
+        Ok(())
+    }
+
+    fn get_checkpoint(&self, id: ModuleId) -> StoreResult<ModuleCheckpoint> {
+        Ok(self.lock().modules.get(&id).map(|module| module.checkpoint).unwrap_or_default())
+    }
+
+    fn get_state(&self, id: ModuleId) -> StoreResult<Option<ModuleState>> {
+        Ok(self.lock().modules.get(&id).map(|module| module.state))
+    }
+
+    fn list(&self) -> StoreResult<Vec<ModuleInfo>> {
+        let inner = self.lock();
+        Ok(inner.modules.iter().map(|(id, module)| inner.info(*id, module)).collect())
+    }
+
+    fn get_info(&self, name: &str) -> StoreResult<Option<ModuleInfo>> {
+        let inner = self.lock();
+        Ok(inner.find(name).map(|(id, module)| inner.info(id, module)))
+    }
+
+    fn get_modules_by_state(&self, state: ModuleState) -> StoreResult<Vec<ModuleId>> {
+        let inner = self.lock();
+        Ok(inner
+            .modules
+            .iter()
+            .filter(|(_, module)| module.state == state)
+            .map(|(id, _)| *id)
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use eyre::Result;
+
+    #[test]
+    fn test_binaries_are_stored_by_hash() -> Result<()> {
+        let store = MemoryStore::new();
+        let first = store.deploy("first", b"binary", None, None)?;
+        let second = store.deploy("second", b"binary", None, None)?;
+        assert_eq!(first.hash, second.hash);
+        assert_eq!(store.lock().binaries.len(), 1);
+
+        store.delete("first")?;
+        assert_eq!(store.lock().binaries.len(), 1);
+        store.delete("second")?;
+        assert!(store.lock().binaries.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_generation() -> Result<()> {
+        let store = MemoryStore::new();
+        store.insert("module", b"binary")?;
+        let generation = store.generation()?;
+        store.set_state("module", ModuleState::Started)?;
+        assert!(store.generation()? > generation);
+
+        // progress of a module isn't a change to reload it for
+        let generation = store.generation()?;
+        let id = store.get_id("module")?.expect("module exists");
+        store.set_last_block(id, 1)?;
+        assert_eq!(store.generation()?, generation);
+        assert_eq!(store.get_checkpoint(id)?.next_block(), Some(2));
+
+        Ok(())
+    }
+}
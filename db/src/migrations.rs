@@ -0,0 +1,313 @@
+//! Versioned schema of the module database.
+//!
+//! Each migration upgrades the schema from the previous version and runs once per database, in
+//! order. Applied versions are recorded in the `schema_version` table. Migrations are never
+//! edited once released: schema changes go in a new one appended to [`MIGRATIONS`].
+
+use eyre::{eyre, Result};
+use rusqlite::{params, Connection, Transaction, TransactionBehavior};
+
+use crate::hex_hash;
+
+/// Step upgrading the schema by one version.
+enum Migration {
+    Sql(&'static str),
+    /// for changes SQL alone can't make, e.g. filling a column computed from others
+    Code(fn(&Transaction<'_>) -> rusqlite::Result<()>),
+}
+
+/// Migrations, the one at index `i` upgrading the schema to version `i + 1`.
+const MIGRATIONS: &[Migration] = &[
+    // 1: initial schema, `IF NOT EXISTS` to adopt databases created before versioning
+    Migration::Sql(
+        "CREATE TABLE IF NOT EXISTS modules (
+            id INTEGER PRIMARY KEY,
+            name TEXT NOT NULL UNIQUE,
+            binary BLOB NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS module_states (
+            module_id INTEGER PRIMARY KEY,
+            state TEXT NOT NULL,
+            FOREIGN KEY(module_id) REFERENCES modules(id)
+        );
+        CREATE TABLE IF NOT EXISTS module_schedules (
+            module_id INTEGER PRIMARY KEY,
+            priority TEXT NOT NULL,
+            weight INTEGER NOT NULL,
+            FOREIGN KEY(module_id) REFERENCES modules(id)
+        );
+        CREATE TABLE IF NOT EXISTS module_checkpoints (
+            module_id INTEGER PRIMARY KEY,
+            start_block INTEGER,
+            last_block INTEGER,
+            FOREIGN KEY(module_id) REFERENCES modules(id)
+        );",
+    ),
+    // 2: generation counter, bumped on every change other processes need to pick up
+    Migration::Sql(
+        "CREATE TABLE generation (
+            id INTEGER PRIMARY KEY CHECK (id = 0),
+            value INTEGER NOT NULL
+        );
+        INSERT INTO generation (id, value) VALUES (0, 0);
+        CREATE TRIGGER modules_insert AFTER INSERT ON modules
+            BEGIN UPDATE generation SET value = value + 1; END;
+        CREATE TRIGGER modules_update AFTER UPDATE ON modules
+            BEGIN UPDATE generation SET value = value + 1; END;
+        CREATE TRIGGER modules_delete AFTER DELETE ON modules
+            BEGIN UPDATE generation SET value = value + 1; END;
+        CREATE TRIGGER module_states_update AFTER UPDATE ON module_states
+            BEGIN UPDATE generation SET value = value + 1; END;
+        CREATE TRIGGER module_schedules_update AFTER UPDATE ON module_schedules
+            BEGIN UPDATE generation SET value = value + 1; END;",
+    ),
+    // 3: binaries moved to immutable versions, the module pointing at the active one
+    Migration::Code(module_versions),
+    // 4: binaries stored once by hash, shared by the versions deploying them
+    Migration::Sql(
+        "CREATE TABLE binaries (
+            hash TEXT PRIMARY KEY,
+            binary BLOB NOT NULL
+        );
+        INSERT OR IGNORE INTO binaries (hash, binary) SELECT hash, binary FROM module_versions;
+        CREATE TABLE module_versions_new (
+            module_id INTEGER NOT NULL,
+            version INTEGER NOT NULL,
+            hash TEXT NOT NULL,
+            deployed_at INTEGER NOT NULL,
+            deployer TEXT,
+            PRIMARY KEY(module_id, version),
+            FOREIGN KEY(module_id) REFERENCES modules(id),
+            FOREIGN KEY(hash) REFERENCES binaries(hash)
+        );
+        INSERT INTO module_versions_new (module_id, version, hash, deployed_at, deployer)
+            SELECT module_id, version, hash, deployed_at, deployer FROM module_versions;
+        DROP TABLE module_versions;
+        ALTER TABLE module_versions_new RENAME TO module_versions;",
+    ),
+    // 5: module metadata, filled from the versions for existing modules
+    Migration::Sql(
+        "ALTER TABLE modules ADD COLUMN owner TEXT;
+        ALTER TABLE modules ADD COLUMN description TEXT;
+        ALTER TABLE modules ADD COLUMN created_at INTEGER NOT NULL DEFAULT 0;
+        ALTER TABLE modules ADD COLUMN updated_at INTEGER NOT NULL DEFAULT 0;
+        ALTER TABLE modules ADD COLUMN fuel_limit INTEGER;
+        ALTER TABLE modules ADD COLUMN memory_limit INTEGER;
+        ALTER TABLE modules ADD COLUMN timeout_ms INTEGER;
+        UPDATE modules SET
+            owner = (SELECT deployer FROM module_versions
+                WHERE module_id = modules.id ORDER BY version LIMIT 1),
+            created_at = (SELECT MIN(deployed_at) FROM module_versions
+                WHERE module_id = modules.id),
+            updated_at = (SELECT MAX(deployed_at) FROM module_versions
+                WHERE module_id = modules.id);
+        ALTER TABLE module_versions ADD COLUMN abi_version INTEGER;
+        ALTER TABLE binaries ADD COLUMN size INTEGER NOT NULL DEFAULT 0;
+        UPDATE binaries SET size = length(binary);
+        CREATE TABLE module_tags (
+            module_id INTEGER NOT NULL,
+            tag TEXT NOT NULL,
+            PRIMARY KEY(module_id, tag),
+            FOREIGN KEY(module_id) REFERENCES modules(id)
+        );
+        CREATE TABLE module_filters (
+            module_id INTEGER NOT NULL,
+            filter TEXT NOT NULL,
+            PRIMARY KEY(module_id, filter),
+            FOREIGN KEY(module_id) REFERENCES modules(id)
+        );",
+    ),
+    // 6: default state, schedule and checkpoint of the modules of databases adopted by 1
+    Migration::Sql(
+        "INSERT OR IGNORE INTO module_states (module_id, state) SELECT id, 'Stopped' FROM modules;
+        INSERT OR IGNORE INTO module_schedules (module_id, priority, weight)
+            SELECT id, 'Normal', 1 FROM modules;
+        INSERT OR IGNORE INTO module_checkpoints (module_id) SELECT id FROM modules;",
+    ),
+];
+
+/// Version of the schema once every migration is applied.
+pub const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+/// Brings the schema up to [`SCHEMA_VERSION`], applying the missing migrations.
+pub(crate) fn migrate(conn: &mut Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS schema_version (
+            version INTEGER PRIMARY KEY,
+            applied_at INTEGER NOT NULL
+        )",
+        [],
+    )?;
+
+    // the write lock is taken upfront so another process opening the database meanwhile waits
+    // for the migrations instead of applying them too
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let version = version(&tx)?;
+    if version > SCHEMA_VERSION {
+        return Err(eyre!(
+            "database schema version {version} is newer than the supported {SCHEMA_VERSION}"
+        ));
+    }
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        match migration {
+            Migration::Sql(sql) => tx.execute_batch(sql)?,
+            Migration::Code(migrate) => migrate(&tx)?,
+        }
+        tx.execute(
+            "INSERT INTO schema_version (version, applied_at) VALUES (?1, strftime('%s', 'now'))",
+            params![index + 1],
+        )?;
+    }
+
+    tx.commit()?;
+    Ok(())
+}
+
+/// Latest migration applied to the database, 0 if none.
+pub(crate) fn version(conn: &Connection) -> rusqlite::Result<u32> {
+    let version: Option<u32> =
+        conn.query_row("SELECT MAX(version) FROM schema_version", [], |row| row.get(0))?;
+    Ok(version.unwrap_or_default())
+}
+
+fn module_versions(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE module_versions (
+            module_id INTEGER NOT NULL,
+            version INTEGER NOT NULL,
+            binary BLOB NOT NULL,
+            hash TEXT NOT NULL,
+            deployed_at INTEGER NOT NULL,
+            deployer TEXT,
+            PRIMARY KEY(module_id, version),
+            FOREIGN KEY(module_id) REFERENCES modules(id)
+        )",
+    )?;
+
+    // existing binaries become the first version of their module
+    let binaries = tx
+        .prepare("SELECT id, binary FROM modules")?
+        .query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, Vec<u8>>(1)?)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    for (id, binary) in binaries {
+        tx.execute(
+            "INSERT INTO module_versions (module_id, version, binary, hash, deployed_at)
+             VALUES (?1, 1, ?2, ?3, strftime('%s', 'now'))",
+            params![id, binary, hex_hash(&binary)],
+        )?;
+    }
+
+    // columns can't be dropped with older SQLite versions, so the table is rebuilt, which drops
+    // its triggers as well
+    tx.execute_batch(
+        "CREATE TABLE modules_new (
+            id INTEGER PRIMARY KEY,
+            name TEXT NOT NULL UNIQUE,
+            active_version INTEGER NOT NULL
+        );
+        INSERT INTO modules_new (id, name, active_version) SELECT id, name, 1 FROM modules;
+        DROP TABLE modules;
+        ALTER TABLE modules_new RENAME TO modules;
+        CREATE TRIGGER modules_insert AFTER INSERT ON modules
+            BEGIN UPDATE generation SET value = value + 1; END;
+        CREATE TRIGGER modules_update AFTER UPDATE ON modules
+            BEGIN UPDATE generation SET value = value + 1; END;
+        CREATE TRIGGER modules_delete AFTER DELETE ON modules
+            BEGIN UPDATE generation SET value = value + 1; END;",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ModuleDatabase, ModuleSchedule, ModuleState, ModuleStore, Priority};
+
+    #[test]
+    fn test_reopen_keeps_modules() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("module.db");
+
+        let db = ModuleDatabase::open(&path)?;
+        assert_eq!(db.schema_version()?, SCHEMA_VERSION);
+        db.insert("module", b"binary")?;
+        db.set_state("module", ModuleState::Started)?;
+        drop(db);
+
+        let db = ModuleDatabase::open(&path)?;
+        assert_eq!(db.schema_version()?, SCHEMA_VERSION);
+        let id = db.get_id("module")?.expect("module still exists");
+        assert_eq!(db.get(id)?, Some(b"binary".to_vec()));
+        assert_eq!(db.get_state(id)?, Some(ModuleState::Started));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_binaries_become_first_versions() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("module.db");
+
+        // database at version 2, binaries stored along modules
+        let mut conn = Connection::open(&path)?;
+        conn.execute(
+            "CREATE TABLE schema_version (version INTEGER PRIMARY KEY, applied_at INTEGER)",
+            [],
+        )?;
+        let tx = conn.transaction()?;
+        for (index, migration) in MIGRATIONS[..2].iter().enumerate() {
+            let Migration::Sql(sql) = migration else { unreachable!() };
+            tx.execute_batch(sql)?;
+            tx.execute(
+                "INSERT INTO schema_version (version, applied_at) VALUES (?1, 0)",
+                params![index + 1],
+            )?;
+        }
+        tx.execute("INSERT INTO modules (name, binary) VALUES ('module', x'0102')", [])?;
+        tx.execute("INSERT INTO module_states (module_id, state) VALUES (1, 'Started')", [])?;
+        tx.commit()?;
+        drop(conn);
+
+        let db = ModuleDatabase::open(&path)?;
+        assert_eq!(db.get(1)?, Some(vec![1, 2]));
+        let versions = db.list_versions("module")?;
+        assert_eq!(versions.len(), 1);
+        assert_eq!(versions[0].hash, hex_hash(&[1, 2]));
+        assert_eq!(db.get_info("module")?.map(|info| info.size), Some(2));
+
+        assert_eq!(db.get_state(1)?, Some(ModuleState::Started));
+
+        // modules adopted from before schedules and checkpoints get the defaults
+        assert_eq!(db.get_schedule(1)?, Some(ModuleSchedule::default()));
+        let schedule = ModuleSchedule { priority: Priority::High, weight: 2 };
+        db.set_schedule("module", schedule)?;
+        assert_eq!(db.get_schedule(1)?, Some(schedule));
+        db.set_start_block("module", Some(10))?;
+        db.set_last_block(1, 12)?;
+        assert_eq!(db.get_checkpoint(1)?.next_block(), Some(13));
+
+        // the generation is still bumped once the table is rebuilt
+        let generation = db.generation()?;
+        db.insert("module", b"new")?;
+        assert!(db.generation()? > generation);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_newer_schema_is_rejected() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("module.db");
+        drop(ModuleDatabase::open(&path)?);
+
+        let conn = Connection::open(&path)?;
+        conn.execute(
+            "INSERT INTO schema_version (version, applied_at) VALUES (?1, 0)",
+            params![SCHEMA_VERSION + 1],
+        )?;
+        drop(conn);
+
+        assert!(ModuleDatabase::open(&path).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,731 @@
+//! SQLite backed [`ModuleStore`], the database shared by the server and the exex.
+
+use eyre::Result;
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{
+    params, types::Type, Connection, ErrorCode, OpenFlags, OptionalExtension, TransactionBehavior,
+};
+use std::{collections::HashMap, path::Path, thread, time::Duration};
+
+use crate::{
+    hex_hash, migrations, unix_timestamp, MetadataUpdate, ModuleBinary, ModuleCheckpoint, ModuleId,
+    ModuleInfo, ModuleMetadata, ModuleSchedule, ModuleState, ModuleStore, ModuleVersion,
+    ResourceLimits, StoreError, StoreResult,
+};
+
+/// How long a connection waits for a lock held by another one, e.g. in another process.
+pub const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+/// Attempts of a query still failing with the database busy after [`BUSY_TIMEOUT`].
+const BUSY_ATTEMPTS: u32 = 3;
+/// Pause before the next attempt, multiplied by the attempts so far.
+const BUSY_BACKOFF: Duration = Duration::from_millis(100);
+
+// database for storing and retrieving wasm modules
+#[derive(Debug, Clone)]
+pub struct ModuleDatabase {
+    pool: Pool<SqliteConnectionManager>,
+}
+
+impl ModuleDatabase {
+    /// Opens the database, creating it if missing, and upgrades its schema. Existing modules
+    /// are kept.
+    ///
+    /// The database can be shared with other processes, e.g. the server and the exex: it is
+    /// opened in WAL mode so readers don't block the writer, and waits up to [`BUSY_TIMEOUT`] for
+    /// a lock held by another connection.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::open_with_busy_timeout(path, BUSY_TIMEOUT)
+    }
+
+    fn open_with_busy_timeout<P: AsRef<Path>>(path: P, busy_timeout: Duration) -> Result<Self> {
+        let manager = SqliteConnectionManager::file(path)
+            .with_flags(OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE)
+            .with_init(move |conn| {
+                conn.busy_timeout(busy_timeout)?;
+                conn.pragma_update(None, "journal_mode", "WAL")?;
+                // durable once the WAL is checkpointed, which is enough for modules
+                conn.pragma_update(None, "synchronous", "NORMAL")
+            });
+        let pool = Pool::new(manager)?;
+
+        migrations::migrate(&mut *pool.get()?)?;
+
+        Ok(ModuleDatabase { pool })
+    }
+
+    /// Version of the database schema, see [`migrations`].
+    pub fn schema_version(&self) -> Result<u32> {
+        Ok(self.with_conn(|conn| migrations::version(conn))?)
+    }
+
+    // modules with the given name, or all of them, along with their metadata
+    fn infos(&self, name: Option<&str>) -> StoreResult<Vec<ModuleInfo>> {
+        self.with_conn(|conn| {
+            let mut stmt = conn.prepare(
+                "SELECT modules.id, modules.name, module_states.state, modules.active_version,
+                    binaries.size, module_versions.hash, module_versions.abi_version,
+                    modules.created_at, modules.updated_at, modules.owner, modules.description,
+                    modules.fuel_limit, modules.memory_limit, modules.timeout_ms
+                 FROM modules
+                 JOIN module_states ON module_states.module_id = modules.id
+                 JOIN module_versions ON module_versions.module_id = modules.id
+                    AND module_versions.version = modules.active_version
+                 JOIN binaries ON binaries.hash = module_versions.hash
+                 WHERE ?1 IS NULL OR modules.name = ?1
+                 ORDER BY modules.id",
+            )?;
+            let rows = stmt.query_map(params![name], |row| {
+                let state: String = row.get(2)?;
+                Ok(ModuleInfo {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    state: state.parse().map_err(|err: eyre::Report| {
+                        rusqlite::Error::FromSqlConversionFailure(2, Type::Text, err.into())
+                    })?,
+                    version: row.get(3)?,
+                    size: row.get(4)?,
+                    hash: row.get(5)?,
+                    abi_version: row.get(6)?,
+                    created_at: row.get(7)?,
+                    updated_at: row.get(8)?,
+                    metadata: ModuleMetadata {
+                        owner: row.get(9)?,
+                        description: row.get(10)?,
+                        filters: Vec::new(),
+                        limits: ResourceLimits {
+                            fuel: row.get(11)?,
+                            memory: row.get(12)?,
+                            timeout: row.get::<_, Option<u64>>(13)?.map(Duration::from_millis),
+                        },
+                        tags: Vec::new(),
+                    },
+                })
+            })?;
+            let mut modules = rows.collect::<rusqlite::Result<Vec<_>>>()?;
+            let indexes: HashMap<ModuleId, usize> =
+                modules.iter().enumerate().map(|(index, module)| (module.id, index)).collect();
+
+            let mut stmt = conn.prepare(
+                "SELECT module_id, tag FROM module_tags
+                 WHERE ?1 IS NULL OR module_id IN (SELECT id FROM modules WHERE name = ?1)
+                 ORDER BY tag",
+            )?;
+            let mut rows = stmt.query(params![name])?;
+            while let Some(row) = rows.next()? {
+                if let Some(&index) = indexes.get(&row.get(0)?) {
+                    modules[index].metadata.tags.push(row.get(1)?);
+                }
+            }
+
+            let mut stmt = conn.prepare(
+                "SELECT module_id, filter FROM module_filters
+                 WHERE ?1 IS NULL OR module_id IN (SELECT id FROM modules WHERE name = ?1)
+                 ORDER BY filter",
+            )?;
+            let mut rows = stmt.query(params![name])?;
+            while let Some(row) = rows.next()? {
+                if let Some(&index) = indexes.get(&row.get(0)?) {
+                    modules[index].metadata.filters.push(row.get(1)?);
+                }
+            }
+
+            Ok(modules)
+        })
+    }
+
+    // runs the queries on a pooled connection, again if another connection held the lock past
+    // the busy timeout
+    fn with_conn<T>(
+        &self,
+        mut queries: impl FnMut(&mut Connection) -> rusqlite::Result<T>,
+    ) -> StoreResult<T> {
+        let mut conn = self.pool.get()?;
+        let mut attempt = 1;
+        loop {
+            match queries(&mut conn) {
+                Err(err) if is_busy(&err) && attempt < BUSY_ATTEMPTS => {
+                    thread::sleep(BUSY_BACKOFF * attempt);
+                    attempt += 1;
+                }
+                result => return Ok(result?),
+            }
+        }
+    }
+}
+
+impl ModuleStore for ModuleDatabase {
+    fn generation(&self) -> StoreResult<u64> {
+        self.with_conn(|conn| conn.query_row("SELECT value FROM generation", [], |row| row.get(0)))
+    }
+
+    fn deploy(
+        &self,
+        name: &str,
+        binary: &[u8],
+        deployer: Option<&str>,
+        abi_version: Option<u32>,
+    ) -> StoreResult<ModuleVersion> {
+        let hash = hex_hash(binary);
+        let deployed_at = unix_timestamp();
+
+        let version = self.with_conn(|conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let module_id: Option<ModuleId> = tx
+                .query_row("SELECT id FROM modules WHERE name = ?1", params![name], |row| {
+                    row.get(0)
+                })
+                .optional()?;
+
+            let (module_id, version) = match module_id {
+                Some(module_id) => {
+                    let version: u32 = tx.query_row(
+                        "SELECT MAX(version) + 1 FROM module_versions WHERE module_id = ?1",
+                        params![module_id],
+                        |row| row.get(0),
+                    )?;
+                    tx.execute(
+                        "UPDATE modules SET active_version = ?1, updated_at = ?2 WHERE id = ?3",
+                        params![version, deployed_at, module_id],
+                    )?;
+                    (module_id, version)
+                }
+                None => {
+                    tx.execute(
+                        "INSERT INTO modules (name, active_version, owner, created_at, updated_at)
+                         VALUES (?1, 1, ?2, ?3, ?3)",
+                        params![name, deployer, deployed_at],
+                    )?;
+                    let module_id = tx.last_insert_rowid();
+                    tx.execute(
+                        "INSERT INTO module_states (module_id, state) VALUES (?1, ?2)",
+                        params![module_id, ModuleState::Stopped.to_string()],
+                    )?;
+                    let schedule = ModuleSchedule::default();
+                    tx.execute(
+                        "INSERT INTO module_schedules (module_id, priority, weight)
+                         VALUES (?1, ?2, ?3)",
+                        params![module_id, schedule.priority.to_string(), schedule.weight],
+                    )?;
+                    tx.execute(
+                        "INSERT INTO module_checkpoints (module_id) VALUES (?1)",
+                        params![module_id],
+                    )?;
+                    (module_id, 1)
+                }
+            };
+
+            tx.execute(
+                "INSERT OR IGNORE INTO binaries (hash, binary, size) VALUES (?1, ?2, ?3)",
+                params![hash, binary, binary.len()],
+            )?;
+            tx.execute(
+                "INSERT INTO module_versions
+                 (module_id, version, hash, deployed_at, deployer, abi_version)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![module_id, version, hash, deployed_at, deployer, abi_version],
+            )?;
+            tx.commit()?;
+            Ok(version)
+        })?;
+
+        Ok(ModuleVersion {
+            version,
+            hash,
+            size: binary.len(),
+            deployed_at,
+            deployer: deployer.map(str::to_string),
+            abi_version,
+            active: true,
+        })
+    }
+
+    fn get_binary(&self, id: ModuleId) -> StoreResult<Option<ModuleBinary>> {
+        self.with_conn(|conn| {
+            conn.query_row(
+                "SELECT modules.active_version, binaries.hash, binaries.binary
+                 FROM modules
+                 JOIN module_versions ON module_versions.module_id = modules.id
+                    AND module_versions.version = modules.active_version
+                 JOIN binaries ON binaries.hash = module_versions.hash
+                 WHERE modules.id = ?1",
+                params![id],
+                |row| {
+                    Ok(ModuleBinary {
+                        version: row.get(0)?,
+                        hash: row.get(1)?,
+                        binary: row.get(2)?,
+                    })
+                },
+            )
+            .optional()
+        })
+    }
+
+    fn get_active_version(&self, id: ModuleId) -> StoreResult<Option<u32>> {
+        self.with_conn(|conn| {
+            conn.query_row("SELECT active_version FROM modules WHERE id = ?1", params![id], |row| {
+                row.get(0)
+            })
+            .optional()
+        })
+    }
+
+    fn list_versions(&self, name: &str) -> StoreResult<Vec<ModuleVersion>> {
+        let versions: Vec<ModuleVersion> = self.with_conn(|conn| {
+            let mut stmt = conn.prepare(
+                "SELECT module_versions.version, module_versions.hash, binaries.size,
+                    module_versions.deployed_at, module_versions.deployer,
+                    module_versions.abi_version,
+                    module_versions.version = modules.active_version
+                 FROM modules
+                 JOIN module_versions ON module_versions.module_id = modules.id
+                 JOIN binaries ON binaries.hash = module_versions.hash
+                 WHERE modules.name = ?1
+                 ORDER BY module_versions.version",
+            )?;
+            let rows = stmt.query_map(params![name], |row| {
+                Ok(ModuleVersion {
+                    version: row.get(0)?,
+                    hash: row.get(1)?,
+                    size: row.get(2)?,
+                    deployed_at: row.get(3)?,
+                    deployer: row.get(4)?,
+                    abi_version: row.get(5)?,
+                    active: row.get(6)?,
+                })
+            })?;
+            rows.collect()
+        })?;
+
+        // a module always has at least one version
+        if versions.is_empty() {
+            return Err(StoreError::NotFound);
+        }
+        Ok(versions)
+    }
+
+    fn promote(&self, name: &str, version: u32) -> StoreResult<()> {
+        let rows_affected = self.with_conn(|conn| {
+            conn.execute(
+                "UPDATE modules
+                 SET active_version = ?1, updated_at = ?2
+                 WHERE name = ?3 AND EXISTS (
+                    SELECT 1 FROM module_versions
+                    WHERE module_id = modules.id AND version = ?1
+                 )",
+                params![version, unix_timestamp(), name],
+            )
+        })?;
+        if rows_affected == 0 {
+            return Err(match self.get_id(name)? {
+                Some(_) => StoreError::VersionNotFound,
+                None => StoreError::NotFound,
+            });
+        }
+        Ok(())
+    }
+
+    fn rollback(&self, name: &str) -> StoreResult<u32> {
+        self.with_conn(|conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let Some((module_id, active_version)) = tx
+                .query_row(
+                    "SELECT id, active_version FROM modules WHERE name = ?1",
+                    params![name],
+                    |row| Ok((row.get::<_, ModuleId>(0)?, row.get::<_, u32>(1)?)),
+                )
+                .optional()?
+            else {
+                return Ok(Err(StoreError::NotFound));
+            };
+
+            let previous: Option<u32> = tx.query_row(
+                "SELECT MAX(version) FROM module_versions WHERE module_id = ?1 AND version < ?2",
+                params![module_id, active_version],
+                |row| row.get(0),
+            )?;
+            let Some(previous) = previous else {
+                return Ok(Err(StoreError::NoEarlierVersion));
+            };
+
+            tx.execute(
+                "UPDATE modules SET active_version = ?1, updated_at = ?2 WHERE id = ?3",
+                params![previous, unix_timestamp(), module_id],
+            )?;
+            tx.commit()?;
+            Ok(Ok(previous))
+        })?
+    }
+
+    fn get_id(&self, name: &str) -> StoreResult<Option<ModuleId>> {
+        self.with_conn(|conn| {
+            conn.query_row("SELECT id FROM modules WHERE name = ?1", params![name], |row| {
+                row.get(0)
+            })
+            .optional()
+        })
+    }
+
+    fn delete(&self, name: &str) -> StoreResult<()> {
+        let deleted = self.with_conn(|conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+            let Some(module_id) = tx
+                .query_row("SELECT id FROM modules WHERE name = ?1", params![name], |row| {
+                    row.get::<_, ModuleId>(0)
+                })
+                .optional()?
+            else {
+                return Ok(false);
+            };
+
+            tx.execute("DELETE FROM module_states WHERE module_id = ?1", params![module_id])?;
+            tx.execute("DELETE FROM module_schedules WHERE module_id = ?1", params![module_id])?;
+            tx.execute("DELETE FROM module_checkpoints WHERE module_id = ?1", params![module_id])?;
+            tx.execute("DELETE FROM module_versions WHERE module_id = ?1", params![module_id])?;
+            tx.execute("DELETE FROM module_tags WHERE module_id = ?1", params![module_id])?;
+            tx.execute("DELETE FROM module_filters WHERE module_id = ?1", params![module_id])?;
+            tx.execute("DELETE FROM modules WHERE id = ?1", params![module_id])?;
+            // binaries no other module deployed
+            tx.execute(
+                "DELETE FROM binaries
+                 WHERE hash NOT IN (SELECT hash FROM module_versions)",
+                [],
+            )?;
+
+            tx.commit()?;
+            Ok(true)
+        })?;
+        if !deleted {
+            return Err(StoreError::NotFound);
+        }
+        Ok(())
+    }
+
+    fn update_metadata(&self, name: &str, update: MetadataUpdate) -> StoreResult<()> {
+        let updated = self.with_conn(|conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            // a column is set if its flag is, to the value following it
+            let rows_affected = tx.execute(
+                "UPDATE modules
+                 SET owner = CASE WHEN ?1 THEN ?2 ELSE owner END,
+                    description = CASE WHEN ?3 THEN ?4 ELSE description END,
+                    fuel_limit = CASE WHEN ?5 THEN ?6 ELSE fuel_limit END,
+                    memory_limit = CASE WHEN ?7 THEN ?8 ELSE memory_limit END,
+                    timeout_ms = CASE WHEN ?9 THEN ?10 ELSE timeout_ms END,
+                    updated_at = ?11
+                 WHERE name = ?12",
+                params![
+                    update.owner.is_some(),
+                    update.owner.as_ref().and_then(Option::as_deref),
+                    update.description.is_some(),
+                    update.description.as_ref().and_then(Option::as_deref),
+                    update.fuel.is_some(),
+                    update.fuel.flatten(),
+                    update.memory.is_some(),
+                    update.memory.flatten(),
+                    update.timeout.is_some(),
+                    update.timeout.flatten().map(|timeout| timeout.as_millis() as u64),
+                    unix_timestamp(),
+                    name
+                ],
+            )?;
+            if rows_affected == 0 {
+                return Ok(false);
+            }
+
+            let module_id: ModuleId =
+                tx.query_row("SELECT id FROM modules WHERE name = ?1", params![name], |row| {
+                    row.get(0)
+                })?;
+            if let Some(tags) = &update.tags {
+                tx.execute("DELETE FROM module_tags WHERE module_id = ?1", params![module_id])?;
+                for tag in tags {
+                    tx.execute(
+                        "INSERT OR IGNORE INTO module_tags (module_id, tag) VALUES (?1, ?2)",
+                        params![module_id, tag],
+                    )?;
+                }
+            }
+            if let Some(filters) = &update.filters {
+                tx.execute("DELETE FROM module_filters WHERE module_id = ?1", params![module_id])?;
+                for filter in filters {
+                    tx.execute(
+                        "INSERT OR IGNORE INTO module_filters (module_id, filter) VALUES (?1, ?2)",
+                        params![module_id, filter],
+                    )?;
+                }
+            }
+
+            tx.commit()?;
+            Ok(true)
+        })?;
+        if !updated {
+            return Err(StoreError::NotFound);
+        }
+        Ok(())
+    }
+
+    fn set_state(&self, name: &str, state: ModuleState) -> StoreResult<()> {
+        let rows_affected = self.with_conn(|conn| {
+            conn.execute(
+                "UPDATE module_states
+                 SET state = ?1
+                 WHERE module_id = (SELECT id FROM modules WHERE name = ?2)",
+                params![state.to_string(), name],
+            )
+        })?;
+        if rows_affected == 0 {
+            return Err(StoreError::NotFound);
+        }
+        Ok(())
+    }
+
+    fn set_schedule(&self, name: &str, schedule: ModuleSchedule) -> StoreResult<()> {
+        if schedule.weight == 0 {
+            return Err(StoreError::InvalidWeight);
+        }
+
+        let rows_affected = self.with_conn(|conn| {
+            conn.execute(
+                "UPDATE module_schedules
+                 SET priority = ?1, weight = ?2
+                 WHERE module_id = (SELECT id FROM modules WHERE name = ?3)",
+                params![schedule.priority.to_string(), schedule.weight, name],
+            )
+        })?;
+        if rows_affected == 0 {
+            return Err(StoreError::NotFound);
+        }
+        Ok(())
+    }
+
+    fn get_schedule(&self, id: ModuleId) -> StoreResult<Option<ModuleSchedule>> {
+        let schedule: Option<(String, u32)> = self.with_conn(|conn| {
+            conn.query_row(
+                "SELECT priority, weight FROM module_schedules WHERE module_id = ?1",
+                params![id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+        })?;
+
+        match schedule {
+            Some((priority, weight)) => {
+                Ok(Some(ModuleSchedule { priority: priority.parse()?, weight }))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn set_start_block(&self, name: &str, start_block: Option<u64>) -> StoreResult<()> {
+        let rows_affected = self.with_conn(|conn| {
+            conn.execute(
+                "UPDATE module_checkpoints
+                 SET start_block = ?1, last_block = NULL
+                 WHERE module_id = (SELECT id FROM modules WHERE name = ?2)",
+                params![start_block, name],
+            )
+        })?;
+        if rows_affected == 0 {
+            return Err(StoreError::NotFound);
+        }
+        Ok(())
+    }
+
+    fn set_last_block(&self, id: ModuleId, block: u64) -> StoreResult<()> {
+        let rows_affected = self.with_conn(|conn| {
+            conn.execute(
+                "UPDATE module_checkpoints SET last_block = ?1 WHERE module_id = ?2",
+                params![block, id],
+            )
+        })?;
+        if rows_affected == 0 {
+            return Err(StoreError::NotFound);
+        }
+        Ok(())
+    }
+
+    fn get_checkpoint(&self, id: ModuleId) -> StoreResult<ModuleCheckpoint> {
+        let checkpoint = self.with_conn(|conn| {
+            conn.query_row(
+                "SELECT start_block, last_block FROM module_checkpoints WHERE module_id = ?1",
+                params![id],
+                |row| Ok(ModuleCheckpoint { start_block: row.get(0)?, last_block: row.get(1)? }),
+            )
+            .optional()
+        })?;
+        Ok(checkpoint.unwrap_or_default())
+    }
+
+    fn get_state(&self, id: ModuleId) -> StoreResult<Option<ModuleState>> {
+        let state: Option<String> = self.with_conn(|conn| {
+            conn.query_row(
+                "SELECT state FROM module_states WHERE module_id = ?1",
+                params![id],
+                |row| row.get(0),
+            )
+            .optional()
+        })?;
+        Ok(state.map(|state| state.parse()).transpose()?)
+    }
+
+    fn list(&self) -> StoreResult<Vec<ModuleInfo>> {
+        self.infos(None)
+    }
+
+    fn get_info(&self, name: &str) -> StoreResult<Option<ModuleInfo>> {
+        Ok(self.infos(Some(name))?.pop())
+    }
+
+    fn get_modules_by_state(&self, state: ModuleState) -> StoreResult<Vec<ModuleId>> {
+        self.with_conn(|conn| {
+            let mut stmt = conn.prepare(
+                "SELECT module_id
+                 FROM module_states
+                 WHERE state = ?1",
+            )?;
+            let rows = stmt.query_map(params![state.to_string()], |row| row.get(0))?;
+            rows.collect()
+        })
+    }
+}
+
+impl From<rusqlite::Error> for StoreError {
+    fn from(err: rusqlite::Error) -> Self {
+        StoreError::Backend(err.into())
+    }
+}
+
+impl From<r2d2::Error> for StoreError {
+    fn from(err: r2d2::Error) -> Self {
+        StoreError::Backend(err.into())
+    }
+}
+
+// whether the database was locked by another connection
+fn is_busy(err: &rusqlite::Error) -> bool {
+    matches!(err.sqlite_error_code(), Some(ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    const TEST_BUSY_TIMEOUT: Duration = Duration::from_millis(50);
+
+    // connection holding the write lock of the database until it commits
+    fn lock(path: &Path) -> Result<Connection> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch("BEGIN IMMEDIATE")?;
+        Ok(conn)
+    }
+
+    #[test]
+    fn test_busy_database_is_retried() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("module.db");
+        let db = ModuleDatabase::open_with_busy_timeout(&path, TEST_BUSY_TIMEOUT)?;
+
+        // released during the backoff after the second attempt, long past the busy timeout
+        let conn = lock(&path)?;
+        let holder = thread::spawn(move || {
+            thread::sleep(TEST_BUSY_TIMEOUT * 2 + BUSY_BACKOFF + BUSY_BACKOFF / 2);
+            conn.execute_batch("COMMIT")
+        });
+        db.insert("module", b"binary")?;
+        holder.join().expect("lock holder panicked")?;
+        assert!(db.get_id("module")?.is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_busy_database_gives_up() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("module.db");
+        let db = ModuleDatabase::open_with_busy_timeout(&path, TEST_BUSY_TIMEOUT)?;
+
+        let conn = lock(&path)?;
+        let started = Instant::now();
+        let result = db.insert("module", b"binary");
+        // every attempt waited for the lock, with the backoff in between
+        assert!(started.elapsed() >= TEST_BUSY_TIMEOUT * BUSY_ATTEMPTS + BUSY_BACKOFF * 3);
+        assert!(matches!(result, Err(StoreError::Backend(_))));
+
+        conn.execute_batch("COMMIT")?;
+        assert_eq!(db.get_id("module")?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_generation_tracks_changes_across_connections() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("module.db");
+        let server = ModuleDatabase::open(&path)?;
+        let exex = ModuleDatabase::open(&path)?;
+        let journal_mode: String =
+            exex.with_conn(|conn| conn.query_row("PRAGMA journal_mode", [], |row| row.get(0)))?;
+        assert_eq!(journal_mode, "wal");
+
+        let generation = exex.generation()?;
+        server.insert("module", b"binary")?;
+        assert!(exex.generation()? > generation);
+
+        let generation = exex.generation()?;
+        server.set_state("module", ModuleState::Started)?;
+        assert!(exex.generation()? > generation);
+
+        // progress of a module isn't a change to reload it for
+        let generation = exex.generation()?;
+        let id = exex.get_id("module")?.expect("module exists");
+        exex.set_last_block(id, 1)?;
+        assert_eq!(exex.generation()?, generation);
+
+        server.delete("module")?;
+        assert!(exex.generation()? > generation);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_binaries_are_stored_by_hash() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let db = ModuleDatabase::open(dir.path().join("module.db"))?;
+        let count_binaries = || {
+            db.with_conn(|conn| {
+                conn.query_row("SELECT COUNT(*) FROM binaries", [], |row| row.get::<_, u32>(0))
+            })
+        };
+
+        let first = db.deploy("first", b"binary", None, None)?;
+        let second = db.deploy("second", b"binary", None, None)?;
+        db.deploy("second", b"binary", None, None)?;
+        assert_eq!(first.hash, second.hash);
+        assert_eq!(count_binaries()?, 1);
+
+        // the binary is kept until no module deploys it anymore
+        db.delete("first")?;
+        let id = db.get_id("second")?.expect("module exists");
+        assert_eq!(db.get(id)?, Some(b"binary".to_vec()));
+        db.delete("second")?;
+        assert_eq!(count_binaries()?, 0);
+
+        db.insert("module", b"binary")?;
+        let id = db.get_id("module")?.expect("module exists");
+        let binary = db.get_binary(id)?.expect("module exists");
+        assert_eq!(binary.hash, hex_hash(b"binary"));
+        assert_eq!(binary.version, 1);
+        binary.verify()?;
+
+        db.with_conn(|conn| conn.execute("UPDATE binaries SET binary = x'00'", []))?;
+        let binary = db.get_binary(id)?.expect("module exists");
+        assert!(binary.verify().is_err());
+
+        Ok(())
+    }
+}
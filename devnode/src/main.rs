@@ -0,0 +1,114 @@
+//! Lightweight node to develop modules locally: it feeds generated or recorded notifications to
+//! the started modules and serves the same deploy/start/stop API as `xxfunc-server`.
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use eyre::Result;
+use tracing::{error, info};
+use xxfunc_db::{
+    call_blocking, MemoryStore, ModuleDatabase, ModuleState, ModuleStore, StoreResult,
+};
+use xxfunc_runtime::{replay, runtime::Runtime, wasm::ModuleRunner};
+use xxfunc_server::{config::ServerConfig, router};
+
+mod synthetic;
+
+/// How long queued and running tasks get to finish on ctrl-c.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Parser)]
+#[clap(name = "xxfunc-devnode", about = "Run xxfunc modules locally, without a reth node")]
+struct Args {
+    #[clap(long, help = "Path of a module database to keep modules in, instead of memory")]
+    db: Option<PathBuf>,
+
+    #[clap(long, default_value = "0.0.0.0", help = "Address the deploy/start/stop API listens on")]
+    bind_address: IpAddr,
+
+    #[clap(long, default_value_t = 3000, help = "Port the deploy/start/stop API listens on")]
+    port: u16,
+
+    #[clap(
+        long,
+        default_value = "2s",
+        value_parser = humantime::parse_duration,
+        help = "Time between two blocks, generated or replayed"
+    )]
+    block_time: Duration,
+
+    #[clap(long, default_value_t = 1, help = "Number of the first generated block")]
+    first_block: u64,
+
+    #[clap(long, default_value_t = 4, help = "Number of logs emitted by each generated block")]
+    logs_per_block: usize,
+
+    #[clap(long, help = "Replay the notifications of a fixture file instead of generating blocks")]
+    replay: Option<PathBuf>,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    tracing_subscriber::fmt::init();
+    let args = Args::parse();
+
+    let module_db: Arc<dyn ModuleStore> = match &args.db {
+        Some(path) => {
+            info!("Module database initialized at {}", path.display());
+            Arc::new(ModuleDatabase::open(path)?)
+        }
+        None => Arc::new(MemoryStore::new()),
+    };
+    let runtime = Runtime::new(Arc::clone(&module_db))?;
+
+    let runner = Arc::new(ModuleRunner::new()?);
+    let app = router(Arc::clone(&module_db), runner, ServerConfig::default().body_limit);
+    let addr = SocketAddr::new(args.bind_address, args.port);
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    info!("API listening on {}", addr);
+    tokio::spawn(async move {
+        if let Err(err) = axum::serve(listener, app).await {
+            error!(%err, "API server failed.");
+        }
+    });
+
+    let mut notifications = match &args.replay {
+        Some(path) => replay::replay(path, args.block_time)?,
+        None => synthetic::generate(args.first_block, args.block_time, args.logs_per_block),
+    };
+
+    loop {
+        tokio::select! {
+            notification = notifications.recv() => {
+                let Some(notification) = notification else {
+                    info!("No more notifications.");
+                    break;
+                };
+                let block = notification.committed_chain().map(|chain| chain.tip().number);
+
+                let notification = Arc::new(notification);
+                let modules = call_blocking(&module_db, |db| {
+                    db.get_modules_by_state(ModuleState::Started)?
+                        .into_iter()
+                        .map(|id| Ok((id, db.get_schedule(id)?.unwrap_or_default())))
+                        .collect::<StoreResult<Vec<_>>>()
+                })
+                .await?;
+                for (id, schedule) in &modules {
+                    // failures are logged by the runtime
+                    let _ = runtime.spawn(*id, *schedule, Arc::clone(&notification)).await;
+                }
+                info!(?block, count = modules.len(), "Scheduled tasks.");
+            }
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+
+    runtime.shutdown(Instant::now() + SHUTDOWN_TIMEOUT).await;
+    Ok(())
+}
@@ -0,0 +1,137 @@
+//! Generated chain, to run modules without a node.
+
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use reth_execution_types::{Chain, ExecutionOutcome};
+use reth_exex_types::ExExNotification;
+use reth_primitives::{
+    logs_bloom, Address, Block, Bytes, Header, Log, Receipt, Receipts, SealedBlockWithSenders,
+    Signature, Transaction, TransactionSigned, TxKind, TxLegacy, TxType, B256,
+};
+use tokio::{sync::mpsc, time};
+
+/// Contract the generated transactions call, and that emits their logs.
+const EMITTER: Address = Address::repeat_byte(0x42);
+const GAS_PER_TRANSACTION: u64 = 21_000;
+const GAS_LIMIT: u64 = 30_000_000;
+
+/// Commits a generated block every `block_time`, starting at `first_block`, with one
+/// transaction emitting a log for each of `logs_per_block`.
+pub fn generate(
+    first_block: u64,
+    block_time: Duration,
+    logs_per_block: usize,
+) -> mpsc::Receiver<ExExNotification> {
+    let (sender, receiver) = mpsc::channel(100);
+
+    tokio::spawn(async move {
+        let mut interval = time::interval(block_time);
+        let mut parent_hash = B256::ZERO;
+        for number in first_block.. {
+            interval.tick().await;
+            let chain = generate_chain(number, parent_hash, logs_per_block);
+            parent_hash = chain.tip().hash();
+
+            let notification = ExExNotification::ChainCommitted { new: Arc::new(chain) };
+            if sender.send(notification).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    receiver
+}
+
+// chain of a single block, whose transactions each emit a log with the block number as data
+fn generate_chain(number: u64, parent_hash: B256, logs: usize) -> Chain {
+    let transactions: Vec<_> = (0..logs as u64)
+        .map(|nonce| {
+            let transaction = Transaction::Legacy(TxLegacy {
+                nonce,
+                gas_limit: GAS_PER_TRANSACTION,
+                to: TxKind::Call(EMITTER),
+                ..Default::default()
+            });
+            TransactionSigned::from_transaction_and_signature(transaction, Signature::default())
+        })
+        .collect();
+
+    let receipts: Vec<_> = (0..logs as u64)
+        .map(|index| Receipt {
+            tx_type: TxType::Legacy,
+            success: true,
+            cumulative_gas_used: GAS_PER_TRANSACTION * (index + 1),
+            logs: vec![Log::new_unchecked(
+                EMITTER,
+                vec![B256::with_last_byte(index as u8)],
+                Bytes::copy_from_slice(&number.to_be_bytes()),
+            )],
+            ..Default::default()
+        })
+        .collect();
+
+    let header = Header {
+        parent_hash,
+        number,
+        timestamp: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |now| now.as_secs()),
+        gas_limit: GAS_LIMIT,
+        gas_used: GAS_PER_TRANSACTION * logs as u64,
+        logs_bloom: logs_bloom(receipts.iter().flat_map(|receipt| &receipt.logs)),
+        ..Default::default()
+    };
+    let senders = vec![Address::ZERO; transactions.len()];
+    let block = Block { header, body: transactions, ..Default::default() }.seal_slow();
+    let block = SealedBlockWithSenders::new(block, senders).expect("one sender per transaction");
+
+    let receipts = Receipts { receipt_vec: vec![receipts.into_iter().map(Some).collect()] };
+    let execution_outcome = ExecutionOutcome::new(Default::default(), receipts, number, Vec::new());
+    Chain::new([block], execution_outcome, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_chain() {
+        let parent_hash = B256::repeat_byte(1);
+        let chain = generate_chain(7, parent_hash, 3);
+
+        // a single block, numbered as asked
+        assert_eq!(chain.first().number, 7);
+        assert_eq!(chain.tip().number, 7);
+        assert_eq!(chain.tip().parent_hash, parent_hash);
+        assert_eq!(chain.execution_outcome().first_block(), 7);
+
+        // one receipt with a log per transaction
+        assert_eq!(chain.tip().body.len(), 3);
+        let receipts = &chain.execution_outcome().receipts().receipt_vec;
+        assert_eq!(receipts.len(), 1);
+        assert_eq!(receipts[0].len(), 3);
+        for receipt in receipts[0].iter().flatten() {
+            assert_eq!(receipt.logs.len(), 1);
+            assert_eq!(receipt.logs[0].data.data, Bytes::copy_from_slice(&7u64.to_be_bytes()));
+        }
+        assert_eq!(chain.tip().gas_used, GAS_PER_TRANSACTION * 3);
+    }
+
+    #[tokio::test]
+    async fn test_generated_chains_are_linked() {
+        let mut notifications = generate(10, Duration::from_millis(1), 2);
+
+        let mut parent_hash = B256::ZERO;
+        for number in 10..15 {
+            let notification = notifications.recv().await.expect("generator keeps going");
+            // blocks only extend the chain, nothing is reverted or reorged
+            assert!(notification.reverted_chain().is_none());
+            let chain = notification.committed_chain().expect("chain is committed");
+            assert_eq!(chain.first().number, number);
+            assert_eq!(chain.tip().number, number);
+            assert_eq!(chain.tip().parent_hash, parent_hash);
+            parent_hash = chain.tip().hash();
+        }
+    }
+}
@@ -0,0 +1,9 @@
+#[xxfunc::main]
+fn main(data: &[u8]) -> Result<(), String> {
+    if data.is_empty() {
+        return Err("exex notification data is empty".to_string());
+    }
+
+    println!("🦀 Hello, world from wasi!, exec notification data length: {:?}", data.len());
+    Ok(())
+}
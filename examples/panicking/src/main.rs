@@ -0,0 +1,6 @@
+#[xxfunc::main]
+fn main(data: &[u8]) {
+    assert!(!data.is_empty(), "exex notification data is empty");
+
+    println!("🦀 Hello, world from wasi!, exec notification data length: {:?}", data.len());
+}
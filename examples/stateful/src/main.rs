@@ -0,0 +1,16 @@
+use std::sync::Mutex;
+
+// only survives between notifications because the module is declared stateful
+static PREVIOUS: Mutex<Vec<u8>> = Mutex::new(Vec::new());
+
+#[xxfunc::main(stateful)]
+fn main(data: &[u8]) -> Result<(), String> {
+    let mut previous = PREVIOUS.lock().unwrap();
+    if previous.as_slice() == data {
+        return Err("received the same notification twice".to_string());
+    }
+    *previous = data.to_vec();
+
+    println!("🦀 Hello, world from wasi!, exec notification data length: {:?}", data.len());
+    Ok(())
+}
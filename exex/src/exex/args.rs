@@ -0,0 +1,92 @@
+use std::{path::PathBuf, time::Duration};
+
+use clap::{builder::RangedU64ValueParser, Args};
+use xxfunc_runtime::{runtime::RuntimeConfig, wasm::ModuleLimits};
+
+/// xxfunc arguments, parsed along with reth's.
+#[derive(Debug, Clone, Args)]
+pub struct XxfuncArgs {
+    /// Path of the module database. Defaults to `xxfunc/module.db` in the node's data directory.
+    #[arg(long = "xxfunc.db", value_name = "PATH")]
+    pub db: Option<PathBuf>,
+
+    /// Number of modules executed concurrently. Defaults to the number of CPUs.
+    #[arg(
+        long = "xxfunc.workers",
+        value_name = "COUNT",
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    pub workers: Option<usize>,
+
+    /// Maximum number of queued executions.
+    #[arg(
+        long = "xxfunc.queue-size",
+        value_name = "COUNT",
+        default_value_t = 1024,
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    pub queue_size: usize,
+
+    /// Fuel, roughly the number of wasm instructions, each execution may use. Unlimited by
+    /// default.
+    #[arg(long = "xxfunc.fuel", value_name = "FUEL")]
+    pub fuel: Option<u64>,
+
+    /// Size of a module's linear memory, in MiB. Unlimited by default.
+    #[arg(long = "xxfunc.memory-limit", value_name = "MIB")]
+    pub memory_limit: Option<usize>,
+
+    /// Time each execution may take, e.g. `30s`. Unlimited by default.
+    #[arg(
+        long = "xxfunc.timeout",
+        value_name = "DURATION",
+        value_parser = humantime::parse_duration
+    )]
+    pub timeout: Option<Duration>,
+
+    /// Feed modules empty mock notifications at the given interval, e.g. `10s`, instead of the
+    /// node's notifications. For development only.
+    #[arg(
+        long = "xxfunc.mock-notifications",
+        value_name = "INTERVAL",
+        value_parser = humantime::parse_duration,
+        conflicts_with = "replay"
+    )]
+    pub mock_notifications: Option<Duration>,
+
+    /// Feed modules the notifications recorded in the fixture file instead of the node's. JSON
+    /// lines for `.json` and `.jsonl` files, bincode otherwise.
+    #[arg(long = "xxfunc.replay", value_name = "PATH")]
+    pub replay: Option<PathBuf>,
+
+    /// Time between two replayed notifications.
+    #[arg(
+        long = "xxfunc.replay-interval",
+        value_name = "INTERVAL",
+        value_parser = humantime::parse_duration,
+        default_value = "1s"
+    )]
+    pub replay_interval: Duration,
+
+    /// Record the notifications fed to modules to the fixture file, in the format given by its
+    /// extension, to be replayed later.
+    #[arg(long = "xxfunc.record", value_name = "PATH")]
+    pub record: Option<PathBuf>,
+}
+
+impl XxfuncArgs {
+    /// Configuration of the runtime executing modules.
+    pub fn runtime_config(&self) -> RuntimeConfig {
+        let default = RuntimeConfig::default();
+        RuntimeConfig {
+            num_workers: self.workers.unwrap_or(default.num_workers),
+            queue_capacity: self.queue_size,
+            limits: ModuleLimits {
+                fuel: self.fuel,
+                memory: self.memory_limit.map(|mib| mib << 20),
+                timeout: self.timeout,
+            },
+            ..default
+        }
+    }
+}
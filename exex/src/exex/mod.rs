@@ -1,3 +1,4 @@
+pub mod args;
 pub mod reth;
 pub mod rpc;
 pub mod scheduler;
@@ -1,21 +1,23 @@
 use std::{sync::Arc, time::Duration};
 
+use clap::Parser;
+use reth::cli::Cli;
 use reth_execution_types::Chain;
 use reth_exex::ExExNotification;
 use tokio::{sync::mpsc, time};
-use tracing::info;
+use tracing::{error, info};
+use xxfunc_db::ModuleDatabase;
+use xxfunc_runtime::replay;
 
 use super::{
+    args::XxfuncArgs,
     rpc::{ExExRpcExt, ExExRpcExtApiServer},
     scheduler::Scheduler,
 };
 
 pub fn init_reth() -> eyre::Result<()> {
-    reth::cli::Cli::parse_args().run(|builder, _| async move {
-        let (rpc_tx, _) = mpsc::unbounded_channel();
-
-        // create fake notification channel to controll notification sender
-        let (notification_sender, notification_receiver) = mpsc::channel(100);
+    Cli::<XxfuncArgs>::parse().run(|builder, args| async move {
+        let (rpc_tx, rpc_rx) = mpsc::unbounded_channel();
 
         let handle = builder
             .node(reth_node_ethereum::EthereumNode::default())
@@ -23,39 +25,70 @@ pub fn init_reth() -> eyre::Result<()> {
                 ctx.modules.merge_configured(ExExRpcExt { to_exex: rpc_tx }.into_rpc())?;
                 Ok(())
             })
-            .install_exex("xx", |mut ctx| async {
-                // override notification receiver
-                ctx.notifications = notification_receiver;
+            .install_exex("xx", move |mut ctx| async move {
+                if let Some(interval) = args.mock_notifications {
+                    info!(?interval, "Replacing exex notifications with mock ones.");
+                    ctx.notifications = mock_notifications(interval);
+                } else if let Some(path) = &args.replay {
+                    ctx.notifications = replay::replay(path, args.replay_interval)?;
+                }
+                if let Some(path) = &args.record {
+                    ctx.notifications = replay::record(ctx.notifications, path)?;
+                }
+
+                let db_path = match &args.db {
+                    Some(path) => path.clone(),
+                    None => {
+                        let dir = ctx.config.datadir().data_dir().join("xxfunc");
+                        std::fs::create_dir_all(&dir)?;
+                        dir.join("module.db")
+                    }
+                };
+                let db = ModuleDatabase::open(&db_path)?;
+                info!(path = %db_path.display(), "Module database opened.");
 
-                Ok(Scheduler::new(ctx)?.start())
+                let synthetic = args.mock_notifications.is_some() || args.replay.is_some();
+                let mut scheduler =
+                    Scheduler::new(ctx, rpc_rx, Arc::new(db), args.runtime_config())?;
+                if synthetic {
+                    // their heights aren't the node's, reth must not prune by them
+                    scheduler = scheduler.with_synthetic_notifications();
+                }
+                Ok(scheduler.start())
             })
             .launch()
             .await?;
 
-        // Start a task to send mock notifications every 10 seconds
-        tokio::spawn(async move {
-            let mut interval = time::interval(Duration::from_secs(10));
-            loop {
-                interval.tick().await;
-                let notification = ExExNotification::ChainCommitted {
-                    new: Arc::new(Chain::from_block(
-                        Default::default(),
-                        Default::default(),
-                        Default::default(),
-                    )),
-                };
-                if let Err(e) = notification_sender.send(notification).await {
-                    eprintln!("Failed to send notification: {}", e);
-                    break;
-                }
-                info!("📢 Sent mock exex notification");
-            }
-        });
-
         handle.wait_for_node_exit().await
     })
 }
 
+// sends an empty notification every `interval`, until the receiver is dropped
+fn mock_notifications(interval: Duration) -> mpsc::Receiver<ExExNotification> {
+    let (notification_sender, notification_receiver) = mpsc::channel(100);
+
+    tokio::spawn(async move {
+        let mut interval = time::interval(interval);
+        loop {
+            interval.tick().await;
+            let notification = ExExNotification::ChainCommitted {
+                new: Arc::new(Chain::from_block(
+                    Default::default(),
+                    Default::default(),
+                    Default::default(),
+                )),
+            };
+            if let Err(e) = notification_sender.send(notification).await {
+                error!(%e, "Failed to send notification.");
+                break;
+            }
+            info!("📢 Sent mock exex notification");
+        }
+    });
+
+    notification_receiver
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -4,80 +4,374 @@ use async_trait::async_trait;
 use jsonrpsee::{
     core::RpcResult,
     proc_macros::rpc,
-    types::{error::INTERNAL_ERROR_CODE, ErrorObject, ErrorObjectOwned},
+    types::{
+        error::{INTERNAL_ERROR_CODE, INVALID_PARAMS_CODE},
+        ErrorObject, ErrorObjectOwned,
+    },
 };
+use serde::Serialize;
 use tokio::sync::{mpsc, oneshot};
+use xxfunc_db::{ModuleSchedule, ModuleVersion, Priority, StoreError};
+
+/// No module with the given name is deployed.
+pub const MODULE_NOT_FOUND_CODE: i32 = -32001;
+// -32002 meant the module already existed, before deploying again added a version. It isn't
+// reused, for clients still matching on it.
+/// The wasm binary doesn't implement the ABI expected by the runtime.
+pub const INVALID_MODULE_CODE: i32 = -32003;
+/// The exex isn't running, so the request couldn't be handled.
+pub const EXEX_UNAVAILABLE_CODE: i32 = -32004;
+/// The node doesn't have the requested block.
+pub const BLOCK_NOT_FOUND_CODE: i32 = -32005;
+/// The module has no version with the given number.
+pub const VERSION_NOT_FOUND_CODE: i32 = -32006;
 
 #[rpc(server, namespace = "exex")]
 trait ExExRpcExtApi {
+    /// Deploys the binary as a new version of the module, created if missing, and activates it.
     #[method(name = "deploy")]
-    async fn deploy(&self, name: String, wasm_base64: String) -> RpcResult<()>;
+    async fn deploy(
+        &self,
+        name: String,
+        wasm_base64: String,
+        deployer: Option<String>,
+    ) -> RpcResult<ModuleVersionSummary>;
 
+    /// Starts the module, processing history from `start_block` if given instead of resuming
+    /// from the last block it processed.
     #[method(name = "start")]
-    async fn start(&self, name: String) -> RpcResult<()>;
+    async fn start(&self, name: String, start_block: Option<u64>) -> RpcResult<()>;
 
     #[method(name = "stop")]
     async fn stop(&self, name: String) -> RpcResult<()>;
+
+    #[method(name = "list")]
+    async fn list(&self) -> RpcResult<Vec<ModuleSummary>>;
+
+    #[method(name = "status")]
+    async fn status(&self, name: String) -> RpcResult<ModuleStatus>;
+
+    #[method(name = "delete")]
+    async fn delete(&self, name: String) -> RpcResult<()>;
+
+    /// Runs the module once against the given block, without affecting its checkpoint.
+    #[method(name = "invoke")]
+    async fn invoke(&self, name: String, block_number: u64) -> RpcResult<InvokeOutput>;
+
+    /// Sets the priority class of the module, `High`, `Normal` or `Low`, and its share of
+    /// execution time within the class.
+    #[method(name = "schedule")]
+    async fn schedule(&self, name: String, priority: String, weight: u32) -> RpcResult<()>;
+
+    #[method(name = "versions")]
+    async fn versions(&self, name: String) -> RpcResult<Vec<ModuleVersionSummary>>;
+
+    /// Makes the module run the given version from its next execution on.
+    #[method(name = "promote")]
+    async fn promote(&self, name: String, version: u32) -> RpcResult<()>;
+
+    /// Activates the version preceding the active one, returning its number.
+    #[method(name = "rollback")]
+    async fn rollback(&self, name: String) -> RpcResult<u32>;
+}
+
+/// Deployed module, as returned by `exex_list`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModuleSummary {
+    pub name: String,
+    pub state: String,
+    /// active version
+    pub version: u32,
+    /// hex encoded SHA-256 of the wasm binary
+    pub hash: String,
+    pub size: usize,
+}
+
+/// Version of a module, as returned by `exex_deploy` and `exex_versions`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModuleVersionSummary {
+    pub version: u32,
+    /// hex encoded SHA-256 of the wasm binary
+    pub hash: String,
+    pub size: usize,
+    /// unix timestamp in seconds
+    pub deployed_at: u64,
+    pub deployer: Option<String>,
+    pub abi_version: Option<u32>,
+    pub active: bool,
+}
+
+impl From<ModuleVersion> for ModuleVersionSummary {
+    fn from(version: ModuleVersion) -> Self {
+        Self {
+            version: version.version,
+            hash: version.hash,
+            size: version.size,
+            deployed_at: version.deployed_at,
+            deployer: version.deployer,
+            abi_version: version.abi_version,
+            active: version.active,
+        }
+    }
+}
+
+/// Progress and health of a module, as returned by `exex_status`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModuleStatus {
+    pub state: String,
+    pub last_processed_block: Option<u64>,
+    /// error of the last failed execution since the node started
+    pub last_error: Option<String>,
+    /// executions since the node started
+    pub executions: u64,
+    pub failures: u64,
+}
+
+/// Outcome of `exex_invoke`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InvokeOutput {
+    /// error returned or raised by the module, `None` if it succeeded
+    pub error: Option<String>,
+    pub stdout: String,
+    pub stderr: String,
 }
 
+/// Message sent to the exex, along with the channel to send its outcome back on.
+pub type RpcRequest = (RpcMessage, oneshot::Sender<RpcResult<RpcResponse>>);
+
 pub struct ExExRpcExt {
-    pub to_exex: mpsc::UnboundedSender<(RpcMessage, oneshot::Sender<RpcResult<()>>)>,
+    pub to_exex: mpsc::UnboundedSender<RpcRequest>,
+}
+
+impl ExExRpcExt {
+    // hands the message to the exex and waits for its response
+    async fn request(&self, message: impl Into<RpcMessage>) -> RpcResult<RpcResponse> {
+        let (tx, rx) = oneshot::channel();
+        self.to_exex.send((message.into(), tx)).map_err(|_| rpc_exex_unavailable())?;
+        rx.await.map_err(|_| rpc_exex_unavailable())?
+    }
+
+    // request expecting no data back
+    async fn request_done(&self, message: ModuleMessage) -> RpcResult<()> {
+        match self.request(message).await? {
+            RpcResponse::Done => Ok(()),
+            response => Err(rpc_unexpected_response(&response)),
+        }
+    }
 }
 
+/// Request of the exex rpc namespace.
 #[derive(Debug)]
 pub enum RpcMessage {
-    Install(String, String),
-    Start(String),
+    /// Runs the module once against the block, re-executed in the background.
+    Invoke(String, u64),
+    Module(ModuleMessage),
+}
+
+/// Request applied in order to the module database and runtime.
+#[derive(Debug)]
+pub enum ModuleMessage {
+    Install(String, String, Option<String>),
+    Start(String, Option<u64>),
     Stop(String),
+    List,
+    Status(String),
+    Delete(String),
+    Schedule(String, ModuleSchedule),
+    Versions(String),
+    Promote(String, u32),
+    Rollback(String),
+}
+
+/// Response of the exex to a [`RpcMessage`].
+#[derive(Debug)]
+pub enum RpcResponse {
+    Done,
+    Modules(Vec<ModuleSummary>),
+    Status(ModuleStatus),
+    Invocation(InvokeOutput),
+    Version(ModuleVersionSummary),
+    Versions(Vec<ModuleVersionSummary>),
+    RolledBack(u32),
+}
+
+impl From<ModuleMessage> for RpcMessage {
+    fn from(message: ModuleMessage) -> Self {
+        RpcMessage::Module(message)
+    }
 }
 
 impl Display for RpcMessage {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            RpcMessage::Install(name, bytecode) => {
+            RpcMessage::Invoke(name, block_number) => {
+                write!(f, "invoke {name} on block {block_number}")
+            }
+            RpcMessage::Module(message) => message.fmt(f),
+        }
+    }
+}
+
+impl Display for ModuleMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ModuleMessage::Install(name, bytecode, _) => {
                 write!(f, "install {name} with bytecode of length {}", bytecode.len())
             }
-            RpcMessage::Start(name) => write!(f, "start {name}"),
-            RpcMessage::Stop(name) => write!(f, "stop {name}"),
+            ModuleMessage::Start(name, None) => write!(f, "start {name}"),
+            ModuleMessage::Start(name, Some(start_block)) => {
+                write!(f, "start {name} from block {start_block}")
+            }
+            ModuleMessage::Stop(name) => write!(f, "stop {name}"),
+            ModuleMessage::List => write!(f, "list"),
+            ModuleMessage::Status(name) => write!(f, "status {name}"),
+            ModuleMessage::Delete(name) => write!(f, "delete {name}"),
+            ModuleMessage::Schedule(name, schedule) => {
+                write!(
+                    f,
+                    "schedule {name} as {} with weight {}",
+                    schedule.priority, schedule.weight
+                )
+            }
+            ModuleMessage::Versions(name) => write!(f, "versions of {name}"),
+            ModuleMessage::Promote(name, version) => {
+                write!(f, "promote {name} to version {version}")
+            }
+            ModuleMessage::Rollback(name) => write!(f, "rollback {name}"),
         }
     }
 }
 
 #[async_trait]
 impl ExExRpcExtApiServer for ExExRpcExt {
-    async fn deploy(&self, name: String, wasm_base64: String) -> RpcResult<()> {
-        let (tx, rx) = oneshot::channel();
-        let _ = self
-            .to_exex
-            .send((RpcMessage::Install(name, wasm_base64), tx))
-            .map_err(|_| rpc_internal_error())?;
-        rx.await.map_err(|_| rpc_internal_error())?
+    async fn deploy(
+        &self,
+        name: String,
+        wasm_base64: String,
+        deployer: Option<String>,
+    ) -> RpcResult<ModuleVersionSummary> {
+        match self.request(ModuleMessage::Install(name, wasm_base64, deployer)).await? {
+            RpcResponse::Version(version) => Ok(version),
+            response => Err(rpc_unexpected_response(&response)),
+        }
     }
 
-    async fn start(&self, name: String) -> RpcResult<()> {
-        let (tx, rx) = oneshot::channel();
-        let _ =
-            self.to_exex.send((RpcMessage::Start(name), tx)).map_err(|_| rpc_internal_error())?;
-        rx.await.map_err(|_| rpc_internal_error())?
+    async fn start(&self, name: String, start_block: Option<u64>) -> RpcResult<()> {
+        self.request_done(ModuleMessage::Start(name, start_block)).await
     }
 
     async fn stop(&self, name: String) -> RpcResult<()> {
-        let (tx, rx) = oneshot::channel();
-        let _ =
-            self.to_exex.send((RpcMessage::Stop(name), tx)).map_err(|_| rpc_internal_error())?;
-        rx.await.map_err(|_| rpc_internal_error())?
+        self.request_done(ModuleMessage::Stop(name)).await
+    }
+
+    async fn list(&self) -> RpcResult<Vec<ModuleSummary>> {
+        match self.request(ModuleMessage::List).await? {
+            RpcResponse::Modules(modules) => Ok(modules),
+            response => Err(rpc_unexpected_response(&response)),
+        }
+    }
+
+    async fn status(&self, name: String) -> RpcResult<ModuleStatus> {
+        match self.request(ModuleMessage::Status(name)).await? {
+            RpcResponse::Status(status) => Ok(status),
+            response => Err(rpc_unexpected_response(&response)),
+        }
+    }
+
+    async fn delete(&self, name: String) -> RpcResult<()> {
+        self.request_done(ModuleMessage::Delete(name)).await
+    }
+
+    async fn invoke(&self, name: String, block_number: u64) -> RpcResult<InvokeOutput> {
+        match self.request(RpcMessage::Invoke(name, block_number)).await? {
+            RpcResponse::Invocation(output) => Ok(output),
+            response => Err(rpc_unexpected_response(&response)),
+        }
+    }
+
+    async fn schedule(&self, name: String, priority: String, weight: u32) -> RpcResult<()> {
+        let priority =
+            priority.parse::<Priority>().map_err(|err| rpc_invalid_params(err.to_string()))?;
+        self.request_done(ModuleMessage::Schedule(name, ModuleSchedule { priority, weight })).await
+    }
+
+    async fn versions(&self, name: String) -> RpcResult<Vec<ModuleVersionSummary>> {
+        match self.request(ModuleMessage::Versions(name)).await? {
+            RpcResponse::Versions(versions) => Ok(versions),
+            response => Err(rpc_unexpected_response(&response)),
+        }
+    }
+
+    async fn promote(&self, name: String, version: u32) -> RpcResult<()> {
+        self.request_done(ModuleMessage::Promote(name, version)).await
+    }
+
+    async fn rollback(&self, name: String) -> RpcResult<u32> {
+        match self.request(ModuleMessage::Rollback(name)).await? {
+            RpcResponse::RolledBack(version) => Ok(version),
+            response => Err(rpc_unexpected_response(&response)),
+        }
+    }
+}
+
+#[inline]
+pub(crate) fn rpc_internal_error(err: impl Display) -> ErrorObjectOwned {
+    ErrorObject::owned(INTERNAL_ERROR_CODE, format!("internal error: {err}"), None::<()>)
+}
+
+#[inline]
+pub(crate) fn rpc_invalid_params(message: impl Into<String>) -> ErrorObjectOwned {
+    ErrorObject::owned(INVALID_PARAMS_CODE, message.into(), None::<()>)
+}
+
+#[inline]
+pub(crate) fn rpc_invalid_module(err: impl Display) -> ErrorObjectOwned {
+    ErrorObject::owned(INVALID_MODULE_CODE, format!("invalid module: {err}"), None::<()>)
+}
+
+#[inline]
+pub(crate) fn rpc_module_not_found(name: &str) -> ErrorObjectOwned {
+    ErrorObject::owned(MODULE_NOT_FOUND_CODE, format!("module `{name}` not found"), None::<()>)
+}
+
+// `version` is the one asked for, if known
+#[inline]
+pub(crate) fn rpc_version_not_found(name: &str, version: Option<u32>) -> ErrorObjectOwned {
+    let message = match version {
+        Some(version) => format!("module `{name}` has no version {version}"),
+        None => format!("module `{name}` version not found"),
+    };
+    ErrorObject::owned(VERSION_NOT_FOUND_CODE, message, None::<()>)
+}
+
+// error of a store call about the named module
+pub(crate) fn rpc_store_error(name: &str, err: StoreError) -> ErrorObjectOwned {
+    match err {
+        StoreError::NotFound => rpc_module_not_found(name),
+        StoreError::VersionNotFound => rpc_version_not_found(name, None),
+        StoreError::NoEarlierVersion => {
+            rpc_invalid_params(format!("module `{name}` has no version before the active one"))
+        }
+        StoreError::InvalidWeight => rpc_invalid_params("module weight must be at least 1"),
+        StoreError::Backend(err) => rpc_internal_error(err),
     }
 }
 
 #[inline]
-fn rpc_internal_error() -> ErrorObjectOwned {
-    ErrorObject::owned(INTERNAL_ERROR_CODE, "internal error", Some(""))
+pub(crate) fn rpc_block_not_found(block_number: u64) -> ErrorObjectOwned {
+    ErrorObject::owned(BLOCK_NOT_FOUND_CODE, format!("block {block_number} not found"), None::<()>)
 }
 
-// macro_rules! rpc_internal_error_format {
-//     ($($arg:tt)*) => {
-//         jsonrpsee::types::error::ErrorObject::owned(jsonrpsee::types::error::INTERNAL_ERROR_CODE, format!($($arg)*), Some(""))
-//     };
-// }
+#[inline]
+fn rpc_unexpected_response(response: &RpcResponse) -> ErrorObjectOwned {
+    rpc_internal_error(format!("unexpected response from exex: {response:?}"))
+}
 
-// pub(crate) use rpc_internal_error_format;
+#[inline]
+fn rpc_exex_unavailable() -> ErrorObjectOwned {
+    ErrorObject::owned(EXEX_UNAVAILABLE_CODE, "exex is not running", None::<()>)
+}
@@ -1,60 +1,729 @@
-use std::sync::Arc;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use eyre::Result;
-use jsonrpsee::tracing::info;
-use reth_exex::{ExExContext, ExExNotification};
+use futures::{
+    future::{join_all, BoxFuture},
+    stream::FuturesOrdered,
+    FutureExt, StreamExt,
+};
+use jsonrpsee::{
+    core::RpcResult,
+    tracing::{error, info},
+};
+use reth_execution_types::Chain;
+use reth_exex::{BackfillJobFactory, ExExContext, ExExEvent, ExExNotification};
 use reth_node_api::FullNodeComponents;
-use xxfunc_db::{ModuleDatabase, ModuleId, ModuleState};
-use xxfunc_runtime::runtime::Runtime;
+use tokio::sync::{mpsc, oneshot};
+use xxfunc_db::{
+    call_blocking, ModuleId, ModuleSchedule, ModuleState, ModuleStore, StoreError, StoreResult,
+};
+use xxfunc_runtime::{
+    abi::ABI_VERSION,
+    runtime::{JoinHandle, Runtime, RuntimeConfig},
+};
+
+use super::rpc::{
+    rpc_block_not_found, rpc_internal_error, rpc_invalid_module, rpc_invalid_params,
+    rpc_module_not_found, rpc_store_error, rpc_version_not_found, InvokeOutput, ModuleMessage,
+    ModuleStatus, ModuleSummary, RpcMessage, RpcRequest, RpcResponse,
+};
+
+/// How long queued and running tasks get to finish once the node shuts down.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Number of re-executed blocks waiting to be queued, before backfills wait for room.
+const BACKFILL_CHANNEL_SIZE: usize = 16;
 
 pub struct Scheduler<N: FullNodeComponents> {
     // handle to the runtime where tasks can be queued
     runtime: Runtime,
-    db: ModuleDatabase,
+    db: Arc<dyn ModuleStore>,
     exex_ctx: ExExContext<N>,
+    // requests of the exex rpc namespace
+    rpc_rx: mpsc::UnboundedReceiver<RpcRequest>,
+    // started modules that already processed the blocks they missed
+    caught_up: HashSet<ModuleId>,
+    // modules whose missed blocks are being re-executed
+    backfills: HashMap<ModuleId, Backfill>,
+    // number of the next backfill, telling its progress apart from that of dropped ones
+    next_backfill: u64,
+    // sends the blocks re-executed by backfills to `backfill_rx`
+    backfill_tx: mpsc::Sender<Backfilled>,
+    // blocks re-executed for the backfills
+    backfill_rx: mpsc::Receiver<Backfilled>,
+    // sends the blocks re-executed for `exex_invoke` requests to `invoke_rx`
+    invoke_tx: mpsc::UnboundedSender<Reexecuted>,
+    // blocks re-executed for `exex_invoke` requests
+    invoke_rx: mpsc::UnboundedReceiver<Reexecuted>,
+    // started modules and their schedule, with the database generation they were loaded at
+    active_modules: Option<(u64, Vec<(ModuleId, ModuleSchedule)>)>,
+    // whether notifications come from the node's chain, so processed heights can be reported
+    node_notifications: bool,
 }
 
 impl<N: FullNodeComponents> Scheduler<N> {
-    pub fn new(exex_ctx: ExExContext<N>) -> Result<Self> {
-        let db = ModuleDatabase::open("module.db")?;
-        let runtime = Runtime::new(db.clone())?;
+    pub fn new(
+        exex_ctx: ExExContext<N>,
+        rpc_rx: mpsc::UnboundedReceiver<RpcRequest>,
+        db: Arc<dyn ModuleStore>,
+        config: RuntimeConfig,
+    ) -> Result<Self> {
+        let runtime = Runtime::with_config(Arc::clone(&db), config)?;
+        let (backfill_tx, backfill_rx) = mpsc::channel(BACKFILL_CHANNEL_SIZE);
+        let (invoke_tx, invoke_rx) = mpsc::unbounded_channel();
+
+        Ok(Self {
+            runtime,
+            exex_ctx,
+            db,
+            rpc_rx,
+            caught_up: HashSet::new(),
+            backfills: HashMap::new(),
+            next_backfill: 0,
+            backfill_tx,
+            backfill_rx,
+            invoke_tx,
+            invoke_rx,
+            active_modules: None,
+            node_notifications: true,
+        })
+    }
 
-        Ok(Self { runtime, exex_ctx, db })
+    /// Stops reporting `FinishedHeight` to the node, for notifications that don't come from its
+    /// chain, e.g. mock or replayed ones.
+    pub fn with_synthetic_notifications(mut self) -> Self {
+        self.node_notifications = false;
+        self
     }
 
     pub async fn start(mut self) -> Result<()> {
+        let shutdown = self.exex_ctx.task_executor().on_shutdown_signal().clone();
+        tokio::pin!(shutdown);
+
+        // notifications being processed, resolving in the order they were received
+        let mut pending_blocks = FuturesOrdered::new();
+        let mut finished_height = None;
+
         loop {
             tokio::select! {
-                Some(notification) = self.exex_ctx.notifications.recv() => {
-                    self.handle_notification(notification).await?;
+                notification = self.exex_ctx.notifications.recv() => {
+                    let Some(notification) = notification else { break };
+                    pending_blocks.push_back(self.handle_notification(notification).await?);
+                }
+                Some(backfilled) = self.backfill_rx.recv() => {
+                    self.handle_backfilled(backfilled).await;
                 }
+                Some(height) = pending_blocks.next() => {
+                    // every earlier notification is done as well, so reth may prune up to here
+                    let Some(height) = height else { continue };
+                    if finished_height.map_or(true, |finished| height > finished) {
+                        finished_height = Some(height);
+                        self.exex_ctx.events.send(ExExEvent::FinishedHeight(height))?;
+                        info!(%height, "Finished height.");
+                    }
+                }
+                Some((message, reply)) = self.rpc_rx.recv() => {
+                    info!(%message, "Received rpc message.");
+                    match message {
+                        // the block is re-executed in the background and the module runs
+                        // alongside notifications instead of holding them up
+                        RpcMessage::Invoke(name, block_number) => {
+                            self.start_invoke(name, block_number, reply);
+                        }
+                        RpcMessage::Module(message) => {
+                            let _ = reply.send(self.handle_rpc_message(message).await);
+                        }
+                    }
+                }
+                Some((id, chain, reply)) = self.invoke_rx.recv() => {
+                    self.invoke(id, chain, reply);
+                }
+                _ = &mut shutdown => break,
             }
         }
-    }
 
-    async fn handle_notification(&self, notification: ExExNotification) -> Result<()> {
-        let count = self.spawn_tasks(notification).await?;
-        info!(%count, "Scheduled tasks.");
+        // stop taking notifications and give the queued ones a chance to run
+        self.runtime.shutdown(Instant::now() + SHUTDOWN_TIMEOUT).await;
         Ok(())
     }
 
-    // spawn tasks on the runtime and return the number of tasks spawned
-    async fn spawn_tasks(&self, notification: ExExNotification) -> Result<usize> {
+    // schedules the notification, returning a future resolving once every module is done with
+    // it, to the tip of the committed chain if any and the notification comes from the node
+    async fn handle_notification(
+        &mut self,
+        notification: ExExNotification,
+    ) -> Result<BoxFuture<'static, Option<u64>>> {
+        let height = notification
+            .committed_chain()
+            .map(|chain| chain.tip().number)
+            .filter(|_| self.node_notifications);
+        let tasks = self.spawn_tasks(notification).await?;
+        info!(count = %tasks.len(), "Scheduled tasks.");
+
+        // a module is done with the block whether it succeeded, failed or was skipped, there are
+        // no retries
+        Ok(async move {
+            join_all(tasks).await;
+            height
+        }
+        .boxed())
+    }
+
+    // spawn tasks on the runtime, preceded by the blocks a module missed when it was just
+    // started, and return futures resolving once each module is done
+    async fn spawn_tasks(
+        &mut self,
+        notification: ExExNotification,
+    ) -> Result<Vec<BoxFuture<'static, ()>>> {
         let exex_notification = Arc::new(notification);
-        let modules = self.get_active_modules()?;
+        let block = processed_block(&exex_notification);
+        let modules = self.get_active_modules().await?;
+
+        // stopped modules catch up again once restarted, their backfill is cancelled
+        self.caught_up.retain(|id| modules.iter().any(|(active, _)| active == id));
+        self.backfills.retain(|id, _| modules.iter().any(|(active, _)| active == id));
+
+        // a revert has nothing to catch up to, modules wait for the next commit
+        let backfill_to = exex_notification
+            .committed_chain()
+            .and_then(|chain| chain.first().number.checked_sub(1));
+
+        let mut tasks = Vec::with_capacity(modules.len());
+        for (id, schedule) in modules {
+            // the notification is queued once the blocks the module missed are
+            if let Some(backfill) = self.backfills.get_mut(&id) {
+                tasks.push(backfill.hold(Arc::clone(&exex_notification)));
+                continue;
+            }
+            if let Some(to) = backfill_to.filter(|_| self.caught_up.insert(id)) {
+                if let Some(from) = missed_blocks(&self.db, id, to).await {
+                    let mut backfill = Backfill::new(self.next_backfill, schedule);
+                    self.next_backfill += 1;
+                    self.start_backfill(id, &backfill, from, to);
+                    tasks.push(backfill.hold(Arc::clone(&exex_notification)));
+                    self.backfills.insert(id, backfill);
+                    continue;
+                }
+            }
+
+            let handle = self.runtime.spawn(id, schedule, Arc::clone(&exex_notification)).await;
+            tasks.push(self.checkpoint(id, block, handle));
+        }
+
+        Ok(tasks)
+    }
+
+    // re-executes the blocks the module missed in the background, sending them to the scheduler
+    // one by one, then reporting the backfill done or failed, until the backfill is dropped
+    fn start_backfill(&self, id: ModuleId, backfill: &Backfill, from: u64, to: u64) {
+        info!(%id, %from, %to, "Backfilling module.");
+        let job = self.backfill_factory().backfill(from..=to);
+        let backfill_tx = self.backfill_tx.clone();
+        let (run, cancelled) = (backfill.run, Arc::clone(&backfill.cancelled));
+
+        // blocks are re-executed from the historical state, which blocks the thread
+        tokio::task::spawn_blocking(move || {
+            for chain in job {
+                if cancelled.load(Ordering::Relaxed) {
+                    return;
+                }
+                let progress = match chain {
+                    Ok(chain) => Progress::Block(chain),
+                    Err(err) => {
+                        error!(%id, %err, "Failed to backfill module.");
+                        let _ = backfill_tx.blocking_send((id, run, Progress::Failed));
+                        return;
+                    }
+                };
+                if backfill_tx.blocking_send((id, run, progress)).is_err() {
+                    return;
+                }
+            }
+            let _ = backfill_tx.blocking_send((id, run, Progress::Done));
+        });
+    }
 
-        let mut count = 0;
-        for id in modules {
-            let result = self.runtime.spawn(id, Arc::clone(&exex_notification));
+    // queues a re-executed block of a module catching up, or the notifications held back once it
+    // caught up
+    async fn handle_backfilled(&mut self, (id, run, progress): Backfilled) {
+        // the backfill was dropped meanwhile, e.g. the module was stopped or deleted
+        let Some(schedule) = self
+            .backfills
+            .get(&id)
+            .filter(|backfill| backfill.run == run)
+            .map(|backfill| backfill.schedule)
+        else {
+            return;
+        };
 
-            count += 1;
+        match progress {
+            Progress::Block(chain) => {
+                let block = chain.tip().number;
+                let notification = ExExNotification::ChainCommitted { new: Arc::new(chain) };
+                let handle = self.runtime.spawn(id, schedule, Arc::new(notification)).await;
+                let checkpoint = self.checkpoint(id, Some(block), handle);
+                if let Some(backfill) = self.backfills.get_mut(&id) {
+                    backfill.checkpoints.push(checkpoint);
+                }
+            }
+            Progress::Done => {
+                let Some(mut backfill) = self.backfills.remove(&id) else { return };
+                info!(%id, "Module backfilled.");
+
+                let mut checkpoints = std::mem::take(&mut backfill.checkpoints);
+                for (notification, processed) in std::mem::take(&mut backfill.held) {
+                    let block = processed_block(&notification);
+                    let handle = self.runtime.spawn(id, schedule, notification).await;
+                    let checkpoint = self.checkpoint(id, block, handle);
+                    checkpoints.push(
+                        async move {
+                            checkpoint.await;
+                            let _ = processed.send(());
+                        }
+                        .boxed(),
+                    );
+                }
+
+                // checkpoints are recorded in order
+                tokio::spawn(async move {
+                    for checkpoint in checkpoints {
+                        checkpoint.await;
+                    }
+                });
+            }
+            Progress::Failed => {
+                // the held notifications are released without running and the checkpoint stays
+                // before the gap, the next notification backfills the module again from there
+                self.reset_catch_up(id);
+                info!(%id, "Module backfill failed, retrying with the next notification.");
+            }
+        }
+    }
+
+    // makes the module catch up again with the next notification, e.g. from a new start block,
+    // dropping its backfill if any
+    fn reset_catch_up(&mut self, id: ModuleId) {
+        self.caught_up.remove(&id);
+        self.backfills.remove(&id);
+    }
+
+    // re-executes historical blocks into chains, as they would have been notified
+    fn backfill_factory(&self) -> BackfillJobFactory<N::Executor, N::Provider> {
+        BackfillJobFactory::new(
+            self.exex_ctx.block_executor().clone(),
+            self.exex_ctx.provider().clone(),
+        )
+    }
+
+    // resolves once the task is done, recording the block the module got to
+    fn checkpoint(
+        &self,
+        id: ModuleId,
+        block: Option<u64>,
+        handle: JoinHandle<Result<()>>,
+    ) -> BoxFuture<'static, ()> {
+        let db = self.db.clone();
+        async move {
+            // a failed, skipped or cancelled task doesn't move the module forward
+            if !matches!(handle.await, Ok(Ok(()))) {
+                return;
+            }
+            if let Some(block) = block {
+                if let Err(err) = call_blocking(&db, move |db| db.set_last_block(id, block)).await {
+                    error!(%id, %err, "Failed to record module checkpoint.");
+                }
+            }
+        }
+        .boxed()
+    }
+
+    // applies a request of the exex rpc namespace to the module database and runtime
+    async fn handle_rpc_message(&mut self, message: ModuleMessage) -> RpcResult<RpcResponse> {
+        match message {
+            ModuleMessage::Install(name, wasm_base64, deployer) => {
+                let bytes = BASE64
+                    .decode(wasm_base64)
+                    .map_err(|err| rpc_invalid_params(format!("invalid base64 wasm: {err}")))?;
+                self.runtime.runner().validate(&bytes).await.map_err(rpc_invalid_module)?;
+
+                let module = name.clone();
+                let version = call_blocking(&self.db, move |db| {
+                    db.deploy(&module, &bytes, deployer.as_deref(), Some(ABI_VERSION))
+                })
+                .await
+                .map_err(rpc_internal_error)?;
+                info!(%name, version = version.version, "Module installed.");
+                return Ok(RpcResponse::Version(version.into()));
+            }
+            ModuleMessage::Start(name, start_block) => {
+                let module = name.clone();
+                let id = call_blocking(&self.db, move |db| {
+                    if let Some(start_block) = start_block {
+                        db.set_start_block(&module, Some(start_block))?;
+                    }
+                    db.set_state(&module, ModuleState::Started)?;
+                    db.get_id(&module)?.ok_or(StoreError::NotFound)
+                })
+                .await
+                .map_err(|err| rpc_store_error(&name, err))?;
+                // even an already started module catches up again, from its new start block
+                self.reset_catch_up(id);
+                info!(%name, ?start_block, "Module started.");
+            }
+            ModuleMessage::Stop(name) => {
+                let module = name.clone();
+                let id = call_blocking(&self.db, move |db| {
+                    db.set_state(&module, ModuleState::Stopped)?;
+                    db.get_id(&module)?.ok_or(StoreError::NotFound)
+                })
+                .await
+                .map_err(|err| rpc_store_error(&name, err))?;
+                self.reset_catch_up(id);
+                info!(%name, "Module stopped.");
+            }
+            ModuleMessage::List => {
+                let modules =
+                    call_blocking(&self.db, |db| db.list()).await.map_err(rpc_internal_error)?;
+                return Ok(RpcResponse::Modules(
+                    modules
+                        .into_iter()
+                        .map(|module| ModuleSummary {
+                            name: module.name,
+                            state: module.state.to_string(),
+                            version: module.version,
+                            hash: module.hash,
+                            size: module.size,
+                        })
+                        .collect(),
+                ));
+            }
+            ModuleMessage::Status(name) => {
+                let id = module_id(&self.db, &name).await?;
+                let (state, checkpoint) = call_blocking(&self.db, move |db| {
+                    Ok((db.get_state(id)?, db.get_checkpoint(id)?))
+                })
+                .await
+                .map_err(rpc_internal_error)?;
+                let state = state.ok_or_else(|| rpc_module_not_found(&name))?;
+                let stats = self.runtime.stats(id);
+                return Ok(RpcResponse::Status(ModuleStatus {
+                    state: state.to_string(),
+                    last_processed_block: checkpoint.last_block,
+                    last_error: stats.last_error,
+                    executions: stats.executions,
+                    failures: stats.failures,
+                }));
+            }
+            ModuleMessage::Delete(name) => {
+                let id = module_id(&self.db, &name).await?;
+                let module = name.clone();
+                call_blocking(&self.db, move |db| db.delete(&module))
+                    .await
+                    .map_err(|err| rpc_store_error(&name, err))?;
+                // ids can be reused by modules deployed later
+                self.runtime.evict(id);
+                self.reset_catch_up(id);
+                info!(%name, "Module deleted.");
+            }
+            ModuleMessage::Schedule(name, schedule) => {
+                let module = name.clone();
+                call_blocking(&self.db, move |db| db.set_schedule(&module, schedule))
+                    .await
+                    .map_err(|err| rpc_store_error(&name, err))?;
+                let ModuleSchedule { priority, weight } = schedule;
+                info!(%name, %priority, %weight, "Module schedule changed.");
+            }
+            ModuleMessage::Versions(name) => {
+                let module = name.clone();
+                let versions = call_blocking(&self.db, move |db| db.list_versions(&module))
+                    .await
+                    .map_err(|err| rpc_store_error(&name, err))?;
+                return Ok(RpcResponse::Versions(versions.into_iter().map(Into::into).collect()));
+            }
+            // the runtime picks up the new version on the module's next execution
+            ModuleMessage::Promote(name, version) => {
+                let module = name.clone();
+                call_blocking(&self.db, move |db| db.promote(&module, version)).await.map_err(
+                    |err| match err {
+                        StoreError::VersionNotFound => rpc_version_not_found(&name, Some(version)),
+                        err => rpc_store_error(&name, err),
+                    },
+                )?;
+                info!(%name, %version, "Module version promoted.");
+            }
+            ModuleMessage::Rollback(name) => {
+                let module = name.clone();
+                let version = call_blocking(&self.db, move |db| db.rollback(&module))
+                    .await
+                    .map_err(|err| rpc_store_error(&name, err))?;
+                info!(%name, %version, "Module rolled back.");
+                return Ok(RpcResponse::RolledBack(version));
+            }
         }
 
-        Ok(count)
+        Ok(RpcResponse::Done)
     }
 
-    // retrieves all the active (ie started) modules from the database
-    fn get_active_modules(&self) -> Result<Vec<ModuleId>> {
-        self.db.get_modules_by_state(ModuleState::Started)
+    // re-executes the block from the historical state in the background, to invoke the module
+    // on it once done
+    fn start_invoke(&self, name: String, block_number: u64, reply: RpcReply) {
+        let db = Arc::clone(&self.db);
+        let mut job = self.backfill_factory().backfill(block_number..=block_number);
+        let invoke_tx = self.invoke_tx.clone();
+
+        // blocks are re-executed from the historical state, which blocks the thread, as does
+        // looking the module up
+        tokio::task::spawn_blocking(move || {
+            let id = match db.get_id(&name) {
+                Ok(Some(id)) => id,
+                Ok(None) => {
+                    let _ = reply.send(Err(rpc_module_not_found(&name)));
+                    return;
+                }
+                Err(err) => {
+                    let _ = reply.send(Err(rpc_internal_error(err)));
+                    return;
+                }
+            };
+            let chain = job
+                .next()
+                .ok_or_else(|| rpc_block_not_found(block_number))
+                .and_then(|chain| chain.map_err(rpc_internal_error));
+            let _ = invoke_tx.send((id, chain, reply));
+        });
+    }
+
+    // runs the module once against the re-executed block, replying with its output once done
+    fn invoke(&self, id: ModuleId, chain: RpcResult<Chain>, reply: RpcReply) {
+        let chain = match chain {
+            Ok(chain) => chain,
+            Err(err) => {
+                let _ = reply.send(Err(err));
+                return;
+            }
+        };
+        let notification = ExExNotification::ChainCommitted { new: Arc::new(chain) };
+
+        let handle = self.runtime.invoke(id, &notification);
+        tokio::spawn(async move {
+            let output = async {
+                let invocation =
+                    handle.await.map_err(rpc_internal_error)?.map_err(rpc_internal_error)?;
+                Ok(RpcResponse::Invocation(InvokeOutput {
+                    error: invocation.error,
+                    stdout: String::from_utf8_lossy(&invocation.stdout).into_owned(),
+                    stderr: String::from_utf8_lossy(&invocation.stderr).into_owned(),
+                }))
+            };
+            let _ = reply.send(output.await);
+        });
+    }
+
+    // retrieves all the active (ie started) modules with their schedule, from the database only
+    // if it changed since they were last loaded, e.g. by the server
+    async fn get_active_modules(&mut self) -> Result<Vec<(ModuleId, ModuleSchedule)>> {
+        let generation = call_blocking(&self.db, |db| db.generation()).await?;
+        match &self.active_modules {
+            Some((loaded_at, modules)) if *loaded_at == generation => Ok(modules.clone()),
+            _ => {
+                let modules = call_blocking(&self.db, |db| {
+                    db.get_modules_by_state(ModuleState::Started)?
+                        .into_iter()
+                        .map(|id| Ok((id, db.get_schedule(id)?.unwrap_or_default())))
+                        .collect::<StoreResult<Vec<_>>>()
+                })
+                .await?;
+                self.active_modules = Some((generation, modules.clone()));
+                Ok(modules)
+            }
+        }
+    }
+}
+
+// The store is called from free functions borrowing only it, as holding a borrow of the
+// scheduler, which isn't `Sync`, across an await would keep its future from being `Send`.
+
+// first block the module missed up to `to`, from its checkpoint or start block
+async fn missed_blocks(db: &Arc<dyn ModuleStore>, id: ModuleId, to: u64) -> Option<u64> {
+    match call_blocking(db, move |db| db.get_checkpoint(id)).await {
+        Ok(checkpoint) => checkpoint.next_block().filter(|from| *from <= to),
+        Err(err) => {
+            error!(%id, %err, "Failed to load module checkpoint, not backfilling it.");
+            None
+        }
+    }
+}
+
+async fn module_id(db: &Arc<dyn ModuleStore>, name: &str) -> RpcResult<ModuleId> {
+    let module = name.to_string();
+    call_blocking(db, move |db| db.get_id(&module))
+        .await
+        .map_err(rpc_internal_error)?
+        .ok_or_else(|| rpc_module_not_found(name))
+}
+
+// last block of the chain once a module processed the notification
+fn processed_block(notification: &ExExNotification) -> Option<u64> {
+    match notification {
+        ExExNotification::ChainCommitted { new } | ExExNotification::ChainReorged { new, .. } => {
+            Some(new.tip().number)
+        }
+        ExExNotification::ChainReverted { old } => old.first().number.checked_sub(1),
+    }
+}
+
+/// Channel to send the outcome of a request of the exex rpc namespace on.
+type RpcReply = oneshot::Sender<RpcResult<RpcResponse>>;
+
+/// Block re-executed for an `exex_invoke` request of the module.
+type Reexecuted = (ModuleId, RpcResult<Chain>, RpcReply);
+
+/// Module re-executing the blocks it missed. Dropping it stops the re-execution.
+struct Backfill {
+    // number of the backfill, sent along with its progress
+    run: u64,
+    // set once the backfill is dropped, for the thread re-executing the blocks to stop
+    cancelled: Arc<AtomicBool>,
+    schedule: ModuleSchedule,
+    // checkpoints of the missed blocks queued so far
+    checkpoints: Vec<BoxFuture<'static, ()>>,
+    // notifications received meanwhile, queued after the missed blocks, with the channel to
+    // report them processed on
+    held: Vec<(Arc<ExExNotification>, oneshot::Sender<()>)>,
+}
+
+impl Backfill {
+    fn new(run: u64, schedule: ModuleSchedule) -> Self {
+        Self {
+            run,
+            cancelled: Arc::new(AtomicBool::new(false)),
+            schedule,
+            checkpoints: Vec::new(),
+            held: Vec::new(),
+        }
+    }
+
+    // holds the notification back, returning a future resolving once the module is done with it
+    fn hold(&mut self, notification: Arc<ExExNotification>) -> BoxFuture<'static, ()> {
+        let (processed_tx, processed_rx) = oneshot::channel();
+        self.held.push((notification, processed_tx));
+        processed_rx.map(|_| ()).boxed()
+    }
+}
+
+impl Drop for Backfill {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Progress of a backfill of the module, sent from the thread re-executing the blocks along
+/// with the number of the backfill.
+type Backfilled = (ModuleId, u64, Progress);
+
+enum Progress {
+    Block(Chain),
+    /// every missed block was sent
+    Done,
+    /// a missed block couldn't be re-executed, none after it are sent
+    Failed,
+}
+
+#[cfg(test)]
+mod tests {
+    use eyre::eyre;
+    use jsonrpsee::types::error::INVALID_PARAMS_CODE;
+    use reth_exex_test_utils::{test_exex_context, Adapter, TestExExHandle};
+    use reth_primitives::{Header, SealedBlock, SealedBlockWithSenders};
+    use xxfunc_db::MemoryStore;
+
+    use super::*;
+    use crate::exex::rpc::INVALID_MODULE_CODE;
+
+    const MINIMAL_MODULE: &[u8] =
+        include_bytes!("../../../examples/minimal/wasm_output/output.wasm");
+
+    // scheduler of the modules in the store, along with the handle to notify it
+    async fn test_scheduler(
+        db: Arc<dyn ModuleStore>,
+    ) -> Result<(Scheduler<Adapter>, TestExExHandle)> {
+        let (ctx, handle) = test_exex_context().await?;
+        let (_, rpc_rx) = mpsc::unbounded_channel();
+        let config = RuntimeConfig { num_workers: 2, ..Default::default() };
+        Ok((Scheduler::new(ctx, rpc_rx, db, config)?, handle))
+    }
+
+    // chain of a single empty block with the given number
+    fn chain(number: u64) -> Chain {
+        let header = Header { number, ..Default::default() }.seal_slow();
+        let block = SealedBlockWithSenders {
+            block: SealedBlock { header, ..Default::default() },
+            senders: Vec::new(),
+        };
+        Chain::from_block(block, Default::default(), Default::default())
+    }
+
+    async fn next_event(handle: &mut TestExExHandle) -> Result<ExExEvent> {
+        tokio::time::timeout(Duration::from_secs(30), handle.events_rx.recv())
+            .await?
+            .ok_or_else(|| eyre!("scheduler stopped"))
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_finished_height() -> Result<()> {
+        let db = Arc::new(MemoryStore::new());
+        for name in ["first", "second"] {
+            db.insert(name, MINIMAL_MODULE)?;
+            db.set_state(name, ModuleState::Started)?;
+        }
+        let (scheduler, mut handle) = test_scheduler(db.clone()).await?;
+        tokio::spawn(scheduler.start());
+
+        // reported once every module processed the block
+        handle.send_notification_chain_committed(chain(1)).await?;
+        assert!(matches!(next_event(&mut handle).await?, ExExEvent::FinishedHeight(1)));
+        for id in db.get_modules_by_state(ModuleState::Started)? {
+            assert_eq!(db.get_checkpoint(id)?.last_block, Some(1));
+        }
+
+        // a lower height, e.g. of a chain committed after a reorg, isn't reported
+        for number in [3, 2, 4] {
+            handle.send_notification_chain_committed(chain(number)).await?;
+        }
+        assert!(matches!(next_event(&mut handle).await?, ExExEvent::FinishedHeight(3)));
+        assert!(matches!(next_event(&mut handle).await?, ExExEvent::FinishedHeight(4)));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_install_rejected() -> Result<()> {
+        let db = Arc::new(MemoryStore::new());
+        let (mut scheduler, _handle) = test_scheduler(db.clone()).await?;
+        let install =
+            |wasm_base64: String| ModuleMessage::Install("module".to_string(), wasm_base64, None);
+
+        let err =
+            scheduler.handle_rpc_message(install("not base64".to_string())).await.unwrap_err();
+        assert_eq!(err.code(), INVALID_PARAMS_CODE);
+
+        // an empty module, without the exports of the ABI
+        let empty = BASE64.encode(b"\0asm\x01\0\0\0");
+        let err = scheduler.handle_rpc_message(install(empty)).await.unwrap_err();
+        assert_eq!(err.code(), INVALID_MODULE_CODE);
+
+        assert_eq!(db.get_id("module")?, None);
+        let response = scheduler.handle_rpc_message(install(BASE64.encode(MINIMAL_MODULE))).await?;
+        assert!(matches!(response, RpcResponse::Version(version) if version.version == 1));
+
+        Ok(())
     }
 }
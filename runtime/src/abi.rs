@@ -0,0 +1,135 @@
+use std::fmt;
+
+use wasmtime::{ExternType, FuncType, Module, ValType};
+
+/// Version of the guest ABI implemented by the runtime. Modules report theirs through the
+/// `xxfunc_abi_version` export generated by `#[xxfunc::main]`.
+pub const ABI_VERSION: u32 = 1;
+
+pub(crate) const MEMORY_EXPORT: &str = "memory";
+pub(crate) const ALLOC_EXPORT: &str = "alloc";
+pub(crate) const DEALLOC_EXPORT: &str = "dealloc";
+pub(crate) const PROCESS_EXPORT: &str = "process";
+pub(crate) const ABI_VERSION_EXPORT: &str = "xxfunc_abi_version";
+/// Optional marker export of modules declared with `#[xxfunc::main(stateful)]`.
+pub(crate) const STATEFUL_EXPORT: &str = "xxfunc_stateful";
+
+/// Import modules linked by the runtime and the names a module may import from them, `None`
+/// allows any name.
+const ALLOWED_IMPORTS: &[(&str, Option<&[&str]>)] =
+    &[("wasi_snapshot_preview1", None), ("xxfunc", Some(&["panic"]))];
+
+/// Reason a module is incompatible with the runtime.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AbiError {
+    /// The binary is not a valid wasm module.
+    InvalidModule(String),
+    /// A required export is missing.
+    MissingExport(&'static str),
+    /// A required export has the wrong type.
+    InvalidExport { name: &'static str, expected: String, found: String },
+    /// The module imports something the runtime doesn't provide.
+    DisallowedImport { module: String, name: String },
+    /// The module was built against another version of the ABI.
+    UnsupportedVersion { expected: u32, found: u32 },
+}
+
+impl fmt::Display for AbiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AbiError::InvalidModule(err) => write!(f, "invalid wasm module: {err}"),
+            AbiError::MissingExport(name) => write!(f, "missing required export `{name}`"),
+            AbiError::InvalidExport { name, expected, found } => {
+                write!(f, "export `{name}` has type `{found}`, expected `{expected}`")
+            }
+            AbiError::DisallowedImport { module, name } => {
+                write!(f, "import `{module}::{name}` is not provided by the runtime")
+            }
+            AbiError::UnsupportedVersion { expected, found } => {
+                write!(f, "module abi version {found} is not supported, expected {expected}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for AbiError {}
+
+/// Statically checks the exports and imports of a compiled module against the ABI. The
+/// version reported by the module can only be checked once it is instantiated, see
+/// [`ModuleRunner::validate`](crate::wasm::ModuleRunner::validate).
+pub fn validate_module(module: &Module) -> Result<(), AbiError> {
+    for import in module.imports() {
+        let allowed = ALLOWED_IMPORTS.iter().any(|(module, names)| {
+            *module == import.module() && names.map_or(true, |names| names.contains(&import.name()))
+        });
+        if !allowed {
+            return Err(AbiError::DisallowedImport {
+                module: import.module().to_string(),
+                name: import.name().to_string(),
+            });
+        }
+    }
+
+    match module.get_export(MEMORY_EXPORT) {
+        Some(ExternType::Memory(_)) => {}
+        Some(ty) => {
+            return Err(AbiError::InvalidExport {
+                name: MEMORY_EXPORT,
+                expected: "memory".to_string(),
+                found: describe(&ty),
+            })
+        }
+        None => return Err(AbiError::MissingExport(MEMORY_EXPORT)),
+    }
+
+    let engine = module.engine();
+    let functions = [
+        (ALLOC_EXPORT, FuncType::new(engine, [ValType::I64], [ValType::I64])),
+        (DEALLOC_EXPORT, FuncType::new(engine, [ValType::I64, ValType::I64], [])),
+        (PROCESS_EXPORT, FuncType::new(engine, [ValType::I64, ValType::I64], [ValType::I64])),
+        (ABI_VERSION_EXPORT, FuncType::new(engine, [], [ValType::I32])),
+    ];
+    for (name, expected) in functions {
+        match module.get_export(name) {
+            Some(ExternType::Func(ty)) if FuncType::eq(&ty, &expected) => {}
+            Some(ty) => {
+                return Err(AbiError::InvalidExport {
+                    name,
+                    expected: expected.to_string(),
+                    found: describe(&ty),
+                })
+            }
+            None => return Err(AbiError::MissingExport(name)),
+        }
+    }
+
+    let expected = FuncType::new(engine, [], []);
+    match module.get_export(STATEFUL_EXPORT) {
+        Some(ExternType::Func(ty)) if FuncType::eq(&ty, &expected) => {}
+        Some(ty) => {
+            return Err(AbiError::InvalidExport {
+                name: STATEFUL_EXPORT,
+                expected: expected.to_string(),
+                found: describe(&ty),
+            })
+        }
+        None => {}
+    }
+
+    Ok(())
+}
+
+/// Whether the module asks for its instance to be kept alive across notifications.
+pub fn is_stateful(module: &Module) -> bool {
+    matches!(module.get_export(STATEFUL_EXPORT), Some(ExternType::Func(_)))
+}
+
+// short description of an extern type for error messages
+fn describe(ty: &ExternType) -> String {
+    match ty {
+        ExternType::Func(ty) => ty.to_string(),
+        ExternType::Global(_) => "global".to_string(),
+        ExternType::Table(_) => "table".to_string(),
+        ExternType::Memory(_) => "memory".to_string(),
+    }
+}
@@ -1,2 +1,8 @@
+pub mod abi;
+mod queue;
+pub mod replay;
 pub mod runtime;
 pub mod wasm;
+
+#[cfg(test)]
+mod test_utils;
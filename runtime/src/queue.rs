@@ -0,0 +1,272 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
+
+use xxfunc_db::{ModuleId, ModuleSchedule, Priority};
+
+/// Execution time credited to a module of weight 1 each time the modules of its priority class
+/// run out of credit.
+const QUANTUM: Duration = Duration::from_millis(10);
+
+/// Queue of pending executions that hands out at most one item per module at a time, in the
+/// order they were pushed. Items of different modules can be in flight concurrently.
+///
+/// Modules of a higher [`Priority`] are always served first. Within a class, modules take turns
+/// in deficit round-robin: each has a credit of execution time, charged with the measured
+/// duration of its items and topped up in proportion to its weight once every module of the
+/// class used it up. A slow module thus gets fewer turns instead of holding back cheap ones.
+#[derive(Debug)]
+pub(crate) struct TaskQueue<T> {
+    /// modules with pending items or one in flight
+    modules: HashMap<ModuleId, ModuleQueue<T>>,
+    /// modules with pending items and none in flight, per priority class, in round-robin order
+    ready: [VecDeque<ModuleId>; 3],
+    /// number of pending items, not counting the ones in flight
+    len: usize,
+}
+
+#[derive(Debug)]
+struct ModuleQueue<T> {
+    /// pending items, oldest first
+    pending: VecDeque<T>,
+    /// whether an item of the module is in flight
+    running: bool,
+    schedule: ModuleSchedule,
+    /// execution time in nanoseconds the module can still use in the current round, negative
+    /// once overdrawn by a slow item
+    credit: i64,
+}
+
+impl<T> ModuleQueue<T> {
+    fn new(schedule: ModuleSchedule) -> Self {
+        let mut module = Self { pending: VecDeque::new(), running: false, schedule, credit: 0 };
+        module.credit = module.quantum();
+        module
+    }
+
+    // credit topped up per round
+    fn quantum(&self) -> i64 {
+        QUANTUM.as_nanos() as i64 * i64::from(self.schedule.weight.max(1))
+    }
+}
+
+impl<T> TaskQueue<T> {
+    pub(crate) fn new() -> Self {
+        Self { modules: HashMap::new(), ready: Default::default(), len: 0 }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the module has pending items.
+    pub(crate) fn contains(&self, module_id: ModuleId) -> bool {
+        self.modules.get(&module_id).is_some_and(|module| !module.pending.is_empty())
+    }
+
+    /// Queues an item behind the previous ones of the same module. The schedule applies from
+    /// the next time the module becomes ready.
+    pub(crate) fn push(&mut self, module_id: ModuleId, schedule: ModuleSchedule, item: T) {
+        let module = self.modules.entry(module_id).or_insert_with(|| ModuleQueue::new(schedule));
+        let is_ready = !module.running && !module.pending.is_empty();
+        if !is_ready {
+            module.schedule = schedule;
+        }
+        module.pending.push_back(item);
+        self.len += 1;
+
+        if !is_ready && !module.running {
+            self.ready[class(module.schedule.priority)].push_back(module_id);
+        }
+    }
+
+    /// Takes the next item of a module that has nothing in flight. The module stays blocked
+    /// until [`TaskQueue::complete`] is called for it.
+    pub(crate) fn pop(&mut self) -> Option<(ModuleId, T)> {
+        let Self { modules, ready, len } = self;
+        let class = ready.iter_mut().find(|class| !class.is_empty())?;
+
+        // once the whole class is out of credit, top it up for as many rounds as it takes the
+        // first module to get some back
+        if class.iter().all(|id| modules[id].credit <= 0) {
+            let rounds = class
+                .iter()
+                .map(|id| -modules[id].credit / modules[id].quantum() + 1)
+                .min()
+                .expect("class is not empty");
+            for id in class.iter() {
+                let module = modules.get_mut(id).expect("ready module is queued");
+                module.credit += rounds * module.quantum();
+            }
+        }
+
+        // modules still out of credit wait for the next round
+        while modules[&class[0]].credit <= 0 {
+            class.rotate_left(1);
+        }
+
+        let module_id = class.pop_front().expect("class is not empty");
+        let module = modules.get_mut(&module_id).expect("ready module is queued");
+        let item = module.pending.pop_front().expect("ready module has pending items");
+        module.running = true;
+        *len -= 1;
+
+        Some((module_id, item))
+    }
+
+    /// Removes the oldest pending item of the module, leaving its in-flight item alone.
+    pub(crate) fn remove_oldest(&mut self, module_id: ModuleId) -> Option<T> {
+        let module = self.modules.get_mut(&module_id)?;
+        let item = module.pending.pop_front()?;
+        if module.pending.is_empty() && !module.running {
+            let module = self.modules.remove(&module_id).expect("module is queued");
+            self.ready[class(module.schedule.priority)].retain(|id| *id != module_id);
+        }
+        self.len -= 1;
+
+        Some(item)
+    }
+
+    /// Removes every pending item, leaving the in-flight ones alone.
+    pub(crate) fn drain(&mut self) -> Vec<T> {
+        self.ready.iter_mut().for_each(VecDeque::clear);
+        self.len = 0;
+        let items = self.modules.values_mut().flat_map(|module| module.pending.drain(..)).collect();
+        self.modules.retain(|_, module| module.running);
+        items
+    }
+
+    /// Marks the in-flight item of the module as done after running for `elapsed`, making its
+    /// next item available. A module without pending items starts afresh next time.
+    pub(crate) fn complete(&mut self, module_id: ModuleId, elapsed: Duration) {
+        let Some(module) = self.modules.get_mut(&module_id).filter(|module| module.running) else {
+            return;
+        };
+        module.running = false;
+        module.credit = module.credit.saturating_sub(elapsed.as_nanos() as i64);
+
+        if module.pending.is_empty() {
+            self.modules.remove(&module_id);
+        } else {
+            self.ready[class(module.schedule.priority)].push_back(module_id);
+        }
+    }
+}
+
+// index of the ready list of the priority class
+fn class(priority: Priority) -> usize {
+    match priority {
+        Priority::High => 0,
+        Priority::Normal => 1,
+        Priority::Low => 2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NORMAL: ModuleSchedule = ModuleSchedule { priority: Priority::Normal, weight: 1 };
+
+    #[test]
+    fn test_one_in_flight_per_module() {
+        let mut queue = TaskQueue::new();
+        queue.push(1, NORMAL, "a1");
+        queue.push(1, NORMAL, "a2");
+        queue.push(2, NORMAL, "b1");
+
+        // modules run in parallel, but never twice at once
+        assert_eq!(queue.pop(), Some((1, "a1")));
+        assert_eq!(queue.pop(), Some((2, "b1")));
+        assert_eq!(queue.pop(), None);
+
+        // the next item is only released once the previous one completed
+        queue.push(2, NORMAL, "b2");
+        queue.complete(1, Duration::ZERO);
+        assert_eq!(queue.pop(), Some((1, "a2")));
+        assert_eq!(queue.pop(), None);
+
+        queue.complete(2, Duration::ZERO);
+        assert_eq!(queue.pop(), Some((2, "b2")));
+        queue.complete(1, Duration::ZERO);
+        queue.complete(2, Duration::ZERO);
+        assert_eq!(queue.pop(), None);
+        assert_eq!(queue.len(), 0);
+    }
+
+    #[test]
+    fn test_remove_oldest() {
+        let mut queue = TaskQueue::new();
+        queue.push(1, NORMAL, "a1");
+        queue.push(1, NORMAL, "a2");
+        queue.push(2, NORMAL, "b1");
+        assert_eq!(queue.len(), 3);
+
+        assert_eq!(queue.remove_oldest(1), Some("a1"));
+        assert_eq!(queue.remove_oldest(1), Some("a2"));
+        assert_eq!(queue.remove_oldest(1), None);
+        assert!(!queue.contains(1));
+        assert_eq!(queue.len(), 1);
+
+        // the removed module is no longer handed out
+        assert_eq!(queue.pop(), Some((2, "b1")));
+        assert_eq!(queue.pop(), None);
+    }
+
+    #[test]
+    fn test_higher_priority_first() {
+        let mut queue = TaskQueue::new();
+        queue.push(1, ModuleSchedule { priority: Priority::Low, ..NORMAL }, "low");
+        queue.push(2, NORMAL, "normal");
+        queue.push(3, ModuleSchedule { priority: Priority::High, ..NORMAL }, "high");
+
+        assert_eq!(queue.pop(), Some((3, "high")));
+        assert_eq!(queue.pop(), Some((2, "normal")));
+        assert_eq!(queue.pop(), Some((1, "low")));
+    }
+
+    // runs `count` items one at a time, each module taking the given time per item, and
+    // returns how many items each module got to run
+    fn run(
+        queue: &mut TaskQueue<()>,
+        costs: &HashMap<ModuleId, Duration>,
+        count: usize,
+    ) -> Vec<usize> {
+        let mut runs = vec![0; costs.len()];
+        for _ in 0..count {
+            let (module_id, ()) = queue.pop().unwrap();
+            runs[module_id as usize] += 1;
+            // modules always have more work
+            queue.push(module_id, queue.modules[&module_id].schedule, ());
+            queue.complete(module_id, costs[&module_id]);
+        }
+        runs
+    }
+
+    #[test]
+    fn test_slow_module_gets_fewer_turns() {
+        let mut queue = TaskQueue::new();
+        for module_id in 0..2 {
+            queue.push(module_id, NORMAL, ());
+        }
+
+        let costs = HashMap::from([(0, Duration::from_secs(5)), (1, Duration::from_millis(5))]);
+        let runs = run(&mut queue, &costs, 1001);
+
+        // the cheap module runs until it used as much time as the slow one
+        assert_eq!(runs, vec![1, 1000]);
+    }
+
+    #[test]
+    fn test_weighted_share() {
+        let mut queue = TaskQueue::new();
+        queue.push(0, NORMAL, ());
+        queue.push(1, ModuleSchedule { weight: 3, ..NORMAL }, ());
+
+        let costs = HashMap::from([(0, QUANTUM), (1, QUANTUM)]);
+        let runs = run(&mut queue, &costs, 400);
+
+        assert_eq!(runs, vec![100, 300]);
+    }
+}
@@ -0,0 +1,180 @@
+//! Recorded notifications, to run modules against real chains without a synced node.
+
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    time::Duration,
+};
+
+use eyre::{Result, WrapErr};
+use reth_exex_types::ExExNotification;
+use tokio::{sync::mpsc, time};
+use tracing::{error, info};
+
+/// Notifications buffered between a source and the scheduler.
+const CHANNEL_CAPACITY: usize = 100;
+
+/// Encoding of a fixture file, picked from its extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FixtureFormat {
+    /// One JSON notification per line, for `.json` and `.jsonl` files. Readable, but can't
+    /// encode chains carrying trie updates.
+    Json,
+    /// bincode encoded notifications back to back, for any other file.
+    Binary,
+}
+
+impl FixtureFormat {
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json" | "jsonl") => FixtureFormat::Json,
+            _ => FixtureFormat::Binary,
+        }
+    }
+}
+
+/// Reads every notification of a fixture file.
+pub fn read_fixture(path: impl AsRef<Path>) -> Result<Vec<ExExNotification>> {
+    let path = path.as_ref();
+    let file =
+        File::open(path).wrap_err_with(|| format!("failed to open fixture {}", path.display()))?;
+    let mut reader = BufReader::new(file);
+
+    let mut notifications = Vec::new();
+    match FixtureFormat::from_path(path) {
+        FixtureFormat::Json => {
+            for line in reader.lines() {
+                let line = line?;
+                if !line.trim().is_empty() {
+                    notifications.push(serde_json::from_str(&line)?);
+                }
+            }
+        }
+        FixtureFormat::Binary => {
+            // values aren't delimited, the file ends where the next one would start
+            while !reader.fill_buf()?.is_empty() {
+                notifications.push(bincode::deserialize_from(&mut reader)?);
+            }
+        }
+    }
+
+    Ok(notifications)
+}
+
+/// Writes notifications to a fixture file as they come.
+pub struct NotificationRecorder {
+    writer: BufWriter<File>,
+    format: FixtureFormat,
+}
+
+impl NotificationRecorder {
+    /// Creates the fixture file, truncating it if it exists.
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::create(path)
+            .wrap_err_with(|| format!("failed to create fixture {}", path.display()))?;
+        Ok(Self { writer: BufWriter::new(file), format: FixtureFormat::from_path(path) })
+    }
+
+    /// Appends the notification, flushed right away so an interrupted recording stays readable.
+    pub fn record(&mut self, notification: &ExExNotification) -> Result<()> {
+        match self.format {
+            FixtureFormat::Json => {
+                serde_json::to_writer(&mut self.writer, notification)?;
+                self.writer.write_all(b"\n")?;
+            }
+            FixtureFormat::Binary => bincode::serialize_into(&mut self.writer, notification)?,
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Sends the notifications of the fixture file on the returned channel, `interval` apart, to be
+/// used in place of the node's. The channel is closed once all of them were sent.
+pub fn replay(
+    path: impl AsRef<Path>,
+    interval: Duration,
+) -> Result<mpsc::Receiver<ExExNotification>> {
+    let notifications = read_fixture(&path)?;
+    info!(
+        count = notifications.len(),
+        path = %path.as_ref().display(),
+        "Replaying recorded notifications."
+    );
+
+    let (sender, receiver) = mpsc::channel(CHANNEL_CAPACITY);
+    tokio::spawn(async move {
+        for notification in notifications {
+            if sender.send(notification).await.is_err() {
+                break;
+            }
+            time::sleep(interval).await;
+        }
+    });
+
+    Ok(receiver)
+}
+
+/// Forwards the notifications to the returned channel, recording each of them to the fixture
+/// file on the way.
+pub fn record(
+    mut notifications: mpsc::Receiver<ExExNotification>,
+    path: impl AsRef<Path>,
+) -> Result<mpsc::Receiver<ExExNotification>> {
+    let mut recorder = NotificationRecorder::create(&path)?;
+    info!(path = %path.as_ref().display(), "Recording notifications.");
+
+    let (sender, receiver) = mpsc::channel(CHANNEL_CAPACITY);
+    tokio::spawn(async move {
+        while let Some(notification) = notifications.recv().await {
+            if let Err(err) = recorder.record(&notification) {
+                error!(%err, "Failed to record notification.");
+            }
+            if sender.send(notification).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    Ok(receiver)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::test_notification;
+
+    #[tokio::test]
+    async fn test_record_and_replay() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let committed = test_notification();
+        let reverted = ExExNotification::ChainReverted {
+            old: committed.committed_chain().expect("chain is committed"),
+        };
+        let notifications = vec![(*committed).clone(), reverted];
+
+        for file_name in ["notifications.jsonl", "notifications.bin"] {
+            let path = dir.path().join(file_name);
+
+            // live notifications go through untouched while being recorded
+            let (sender, live) = mpsc::channel(CHANNEL_CAPACITY);
+            let mut recorded = record(live, &path)?;
+            for notification in &notifications {
+                sender.send(notification.clone()).await?;
+                assert_eq!(recorded.recv().await.as_ref(), Some(notification));
+            }
+            drop(sender);
+            assert!(recorded.recv().await.is_none());
+
+            let mut replayed = replay(&path, Duration::ZERO)?;
+            for notification in &notifications {
+                assert_eq!(replayed.recv().await.as_ref(), Some(notification));
+            }
+            assert!(replayed.recv().await.is_none());
+        }
+
+        Ok(())
+    }
+}
@@ -1,15 +1,32 @@
-use std::{collections::VecDeque, sync::Arc, thread::Thread};
-
-use eyre::Result;
+use std::{
+    collections::HashMap,
+    fmt,
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use eyre::{eyre, OptionExt, Result};
 use futures::channel::oneshot;
 use parking_lot::Mutex;
 use reth_exex_types::ExExNotification;
-use std::thread;
-use tracing::info;
+use tokio::{
+    sync::{mpsc, Notify},
+    task::JoinSet,
+};
+use tracing::{error, info, warn};
 use wasmtime::Module;
-use xxfunc_db::{ModuleDatabase, ModuleId};
+use xxfunc_db::{call_blocking, ModuleId, ModuleSchedule, ModuleStore};
 
-use crate::wasm::ModuleRunner;
+use crate::{
+    abi::{self, AbiError},
+    queue::TaskQueue,
+    wasm::{Invocation, ModuleError, ModuleInstance, ModuleLimits, ModuleRunner},
+};
 
 #[derive(Debug)]
 pub struct JoinHandle<T>(oneshot::Receiver<T>);
@@ -25,113 +42,528 @@ impl<T> std::future::Future for JoinHandle<T> {
     }
 }
 
+/// Limits after which the instance of a stateful module is replaced by a fresh one.
+#[derive(Debug, Clone, Copy)]
+pub struct StatefulConfig {
+    /// number of notifications processed by the same instance
+    pub max_calls: u64,
+    /// size of the instance's linear memory in bytes
+    pub max_memory: usize,
+}
+
+impl Default for StatefulConfig {
+    fn default() -> Self {
+        Self { max_calls: 10_000, max_memory: 512 * 1024 * 1024 }
+    }
+}
+
+/// What to do with a new task when the queue is at capacity.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Wait for room in the queue, holding back the scheduler.
+    #[default]
+    Block,
+    /// Drop the oldest queued task of the same module to make room.
+    DropOldest,
+    /// Don't queue the task, skipping the module for this notification.
+    SkipModule,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RuntimeConfig {
+    /// number of modules executed concurrently, at least 1
+    pub num_workers: usize,
+    /// recycling of stateful module instances
+    pub stateful: StatefulConfig,
+    /// maximum number of queued tasks, not counting the ones being executed
+    pub queue_capacity: usize,
+    /// how to handle tasks spawned while the queue is full
+    pub overflow_policy: OverflowPolicy,
+    /// resources each execution may use
+    pub limits: ModuleLimits,
+}
+
+impl Default for RuntimeConfig {
+    fn default() -> Self {
+        Self {
+            num_workers: thread::available_parallelism().map_or(1, NonZeroUsize::get),
+            stateful: StatefulConfig::default(),
+            queue_capacity: 1024,
+            overflow_policy: OverflowPolicy::default(),
+            limits: ModuleLimits::default(),
+        }
+    }
+}
+
+/// Reason a queued task was not executed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueueError {
+    /// The queue was full and the task was not queued.
+    Full,
+    /// The task was dropped to make room for a newer task of the same module.
+    Dropped,
+    /// The runtime was shut down before the task got executed.
+    Closed,
+}
+
+impl fmt::Display for QueueError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueueError::Full => write!(f, "task queue is full"),
+            QueueError::Dropped => write!(f, "task dropped for a newer one"),
+            QueueError::Closed => write!(f, "runtime shut down"),
+        }
+    }
+}
+
+impl std::error::Error for QueueError {}
+
 struct Task {
-    module_id: ModuleId,
     exex_notification: Arc<ExExNotification>,
     result_sender: oneshot::Sender<Result<()>>,
 }
 
+/// Outcome of the executions of a module since the runtime started.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ModuleStats {
+    pub executions: u64,
+    pub failures: u64,
+    /// error of the last failed execution
+    pub last_error: Option<String>,
+}
+
+/// How long [`Runtime::shutdown`] waits for the worker threads once the workers stopped. A module
+/// that doesn't yield, e.g. stuck in a loop, keeps its thread busy past this.
+const THREADS_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+
 pub struct Runtime {
     inner: Arc<Inner>,
+    /// tasks executing the queue
+    workers: JoinSet<()>,
+    /// runs the workers, shut down when the runtime is dropped
+    tokio_runtime: Option<tokio::runtime::Runtime>,
 }
 
 struct Inner {
     /// runner for executing user modules
     runner: ModuleRunner,
-    /// database to fetch modules
-    module_db: ModuleDatabase,
-    /// Tasks queue
-    tasks: Mutex<VecDeque<Task>>,
-    /// workers pool
-    workers: Mutex<Vec<Thread>>,
-    /// to spawn the module execution on bcs to support async
-    tokio_runtime: tokio::runtime::Runtime,
+    /// store to fetch modules from
+    module_db: Arc<dyn ModuleStore>,
+    /// Tasks queue, running at most one task per module at a time
+    tasks: Mutex<TaskQueue<Task>>,
+    /// notified when a task is queued
+    work: Notify,
+    /// notified when a task leaves the queue
+    space: Notify,
+    /// set once the runtime is shutting down, workers stop when the queue is drained
+    closed: AtomicBool,
+    /// dedicated workers of the stateful modules
+    stateful_workers: Mutex<StatefulWorkers>,
+    /// executions of each module
+    stats: Mutex<HashMap<ModuleId, ModuleStats>>,
+    config: RuntimeConfig,
 }
 
-impl Runtime {
-    pub fn new(module_db: ModuleDatabase) -> Result<Self> {
-        let num_workers = thread::available_parallelism()?.get();
-        let runner = ModuleRunner::new()?;
-        let tasks = Mutex::new(VecDeque::new());
-        let workers = Mutex::new(Vec::with_capacity(num_workers));
-        let tokio_runtime = tokio::runtime::Builder::new_multi_thread().enable_io().build()?;
+#[derive(Default)]
+struct StatefulWorkers {
+    workers: HashMap<ModuleId, StatefulWorker>,
+    /// bumped by every eviction, so a worker started meanwhile for a deleted module isn't kept
+    evictions: u64,
+}
 
-        let inner = Arc::new(Inner { runner, workers, tasks, module_db, tokio_runtime });
+/// Worker owning the live instance of a stateful module. It stops once every sender is dropped.
+#[derive(Clone)]
+struct StatefulWorker {
+    sender: mpsc::Sender<StatefulTask>,
+    /// version of the module the worker runs
+    version: u32,
+}
 
-        for _ in 0..num_workers {
-            let inner = Arc::clone(&inner);
-
-            thread::spawn(move || {
-                loop {
-                    while let Some(task) = inner.tasks.lock().pop_front() {
-                        // get module from db
-                        let bytes = inner.module_db.get(task.module_id).unwrap().unwrap();
-
-                        // deserialize module
-                        let engine = inner.runner.engine();
-                        let module = Module::from_binary(engine, &bytes).unwrap();
-                        let serialized_notification =
-                        // Can't even do JSON encode of a full struct smh, "key must be a string"
-                        serde_json::to_vec(&task.exex_notification.committed_chain().map(|chain| chain.tip().header.clone())).unwrap();
-
-                        // execute the module on the tokio runtime because it's async
-                        let func = inner.runner.execute(module, serialized_notification);
-
-                        let module_id = task.module_id;
-                        inner.tokio_runtime.block_on(async move {
-                            info!(%module_id, "Executing module.");
-                            let res = func.await;
-                            let _ = task.result_sender.send(res);
-                        });
-                    }
+struct StatefulTask {
+    input: Vec<u8>,
+    result_sender: oneshot::Sender<Result<()>>,
+}
 
-                    // park thread if no tasks
-                    let handle = thread::current();
-                    inner.workers.lock().push(handle);
-                    thread::park();
-                }
-            });
+impl Runtime {
+    pub fn new(module_db: Arc<dyn ModuleStore>) -> Result<Self> {
+        Self::with_config(module_db, RuntimeConfig::default())
+    }
+
+    pub fn with_config(module_db: Arc<dyn ModuleStore>, config: RuntimeConfig) -> Result<Self> {
+        let num_workers = config.num_workers;
+        if num_workers == 0 {
+            return Err(eyre!("runtime needs at least one worker"));
+        }
+        let runner = ModuleRunner::with_limits(config.limits)?;
+        let tasks = Mutex::new(TaskQueue::new());
+
+        // modules are executed on their own runtime so they don't hold up the caller's
+        let tokio_runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(num_workers)
+            .thread_name("xxfunc-worker")
+            .enable_all()
+            .build()?;
+
+        let inner = Arc::new(Inner {
+            runner,
+            tasks,
+            work: Notify::new(),
+            space: Notify::new(),
+            closed: AtomicBool::new(false),
+            module_db,
+            stateful_workers: Mutex::default(),
+            stats: Mutex::new(HashMap::new()),
+            config,
+        });
+
+        let mut workers = JoinSet::new();
+        for _ in 0..num_workers {
+            workers.spawn_on(Arc::clone(&inner).run_worker(), tokio_runtime.handle());
         }
 
-        Ok(Self { inner })
+        Ok(Self { inner, workers, tokio_runtime: Some(tokio_runtime) })
     }
 
-    pub fn spawn(
+    /// Queues the execution of the module for the notification, scheduled against the other
+    /// modules' tasks according to `schedule`. When the queue is full this follows the configured
+    /// [`OverflowPolicy`], so it may wait for room.
+    pub async fn spawn(
         &self,
         module_id: ModuleId,
+        schedule: ModuleSchedule,
         exex_notification: Arc<ExExNotification>,
     ) -> JoinHandle<Result<()>> {
         let (result_sender, rx) = oneshot::channel();
 
         // create task
-        let task = Task { module_id, exex_notification, result_sender };
-        self.inner.tasks.lock().push_back(task);
+        let task = Task { exex_notification, result_sender };
+
+        loop {
+            // room made before we start waiting leaves a permit, so it isn't missed
+            let space = self.inner.space.notified();
+
+            {
+                let mut tasks = self.inner.tasks.lock();
+                if tasks.len() < self.inner.config.queue_capacity {
+                    tasks.push(module_id, schedule, task);
+                    record_queue_depth(&tasks);
+                    break;
+                }
+
+                match self.inner.config.overflow_policy {
+                    OverflowPolicy::Block => {}
+                    OverflowPolicy::DropOldest if tasks.contains(module_id) => {
+                        let dropped = tasks.remove_oldest(module_id).expect("module has tasks");
+                        warn!(%module_id, "Task queue full, dropping oldest task of module.");
+                        let _ = dropped.result_sender.send(Err(QueueError::Dropped.into()));
+                        tasks.push(module_id, schedule, task);
+                        break;
+                    }
+                    OverflowPolicy::DropOldest | OverflowPolicy::SkipModule => {
+                        warn!(%module_id, "Task queue full, skipping module.");
+                        let _ = task.result_sender.send(Err(QueueError::Full.into()));
+                        return JoinHandle(rx);
+                    }
+                }
+            }
+
+            space.await;
+        }
 
-        // wake up available worker
-        self.wake();
+        // wake up an idle worker, or leave a permit for the next one to finish
+        self.inner.work.notify_one();
 
         JoinHandle(rx)
     }
 
-    fn wake(&self) {
-        if let Some(worker) = self.inner.workers.lock().pop() {
-            worker.unpark();
+    /// Runs the module once against the notification on a fresh instance, bypassing the queue,
+    /// and captures its output. Doesn't count towards the module's [`ModuleStats`].
+    pub fn invoke(
+        &self,
+        module_id: ModuleId,
+        exex_notification: &ExExNotification,
+    ) -> JoinHandle<Result<Invocation>> {
+        let (result_sender, rx) = oneshot::channel();
+        let inner = Arc::clone(&self.inner);
+        let input = serialize_notification(exex_notification);
+
+        let tokio_runtime = self.tokio_runtime.as_ref().expect("runtime is running");
+        tokio_runtime.spawn(async move {
+            let result = match inner.load_module(module_id).await {
+                Ok((module, _)) => inner.runner.invoke(module, input).await,
+                Err(err) => Err(err),
+            };
+            let _ = result_sender.send(result);
+        });
+
+        JoinHandle(rx)
+    }
+
+    /// Execution statistics of the module.
+    pub fn stats(&self, module_id: ModuleId) -> ModuleStats {
+        self.inner.stats.lock().get(&module_id).cloned().unwrap_or_default()
+    }
+
+    /// Forgets the live instance and statistics of a module, e.g. once it is deleted. A running
+    /// execution finishes on the instance, which is dropped afterwards.
+    pub fn evict(&self, module_id: ModuleId) {
+        let mut stateful_workers = self.inner.stateful_workers.lock();
+        stateful_workers.workers.remove(&module_id);
+        stateful_workers.evictions += 1;
+        drop(stateful_workers);
+        self.inner.stats.lock().remove(&module_id);
+    }
+
+    /// Runner used to execute modules, e.g. to validate one before it is deployed.
+    pub fn runner(&self) -> &ModuleRunner {
+        &self.inner.runner
+    }
+
+    /// Number of tasks waiting to be executed.
+    pub fn queue_depth(&self) -> usize {
+        self.inner.tasks.lock().len()
+    }
+
+    /// Stops the runtime once the queued tasks have been executed. Tasks still queued or running
+    /// at the deadline are cancelled, then the worker threads are joined.
+    pub async fn shutdown(mut self, deadline: Instant) {
+        info!(queued = self.queue_depth(), "Shutting down runtime.");
+        self.inner.close();
+
+        let drained = tokio::time::timeout_at(deadline.into(), async {
+            while self.workers.join_next().await.is_some() {}
+        })
+        .await;
+
+        if drained.is_err() {
+            warn!("Runtime shutdown deadline reached, cancelling remaining tasks.");
+            // running executions are dropped at their next await point, which resolves their
+            // handles as cancelled
+            self.workers.abort_all();
+            for task in self.inner.tasks.lock().drain() {
+                let _ = task.result_sender.send(Err(QueueError::Closed.into()));
+            }
+        }
+
+        if let Some(tokio_runtime) = self.tokio_runtime.take() {
+            let _ = tokio::task::spawn_blocking(move || {
+                tokio_runtime.shutdown_timeout(THREADS_SHUTDOWN_TIMEOUT)
+            })
+            .await;
         }
+        info!("Runtime shut down.");
     }
 }
 
+impl Drop for Runtime {
+    fn drop(&mut self) {
+        if let Some(tokio_runtime) = self.tokio_runtime.take() {
+            tokio_runtime.shutdown_background();
+        }
+    }
+}
+
+impl Inner {
+    // executes queued tasks, waiting for new ones once the queue is drained, until the runtime
+    // is closed
+    async fn run_worker(self: Arc<Self>) {
+        loop {
+            // registered before looking at the queue, so a task queued or the runtime closing
+            // in between isn't missed
+            let work = self.work.notified();
+            tokio::pin!(work);
+            work.as_mut().enable();
+
+            let Some((module_id, task)) = self.pop_task() else {
+                if self.closed.load(Ordering::Acquire) {
+                    return;
+                }
+                work.await;
+                continue;
+            };
+
+            info!(%module_id, "Executing module.");
+            let started_at = Instant::now();
+            let serialized_notification = serialize_notification(&task.exex_notification);
+            let res = self.execute(module_id, serialized_notification).await;
+            let elapsed = started_at.elapsed();
+            if let Err(err) = &res {
+                error!(%module_id, %err, "Module execution failed.");
+            }
+            self.record_execution(module_id, &res);
+            let _ = task.result_sender.send(res);
+
+            // release the next task of the module, charging it for the time it took
+            self.tasks.lock().complete(module_id, elapsed);
+        }
+    }
+
+    fn record_execution(&self, module_id: ModuleId, result: &Result<()>) {
+        let mut stats = self.stats.lock();
+        let stats = stats.entry(module_id).or_default();
+        stats.executions += 1;
+        if let Err(err) = result {
+            stats.failures += 1;
+            stats.last_error = Some(err.to_string());
+        }
+    }
+
+    // stops accepting work and wakes up idle workers so they can exit
+    fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+        self.work.notify_waiters();
+    }
+
+    // takes the next task off the queue, making room for a new one
+    fn pop_task(&self) -> Option<(ModuleId, Task)> {
+        let mut tasks = self.tasks.lock();
+        let task = tasks.pop()?;
+        record_queue_depth(&tasks);
+        self.space.notify_one();
+        Some(task)
+    }
+
+    // runs the input on a fresh instance, or hands it to the dedicated worker of a stateful module
+    async fn execute(self: &Arc<Self>, module_id: ModuleId, input: Vec<u8>) -> Result<()> {
+        let (worker, evictions) = {
+            let stateful_workers = self.stateful_workers.lock();
+            (stateful_workers.workers.get(&module_id).cloned(), stateful_workers.evictions)
+        };
+        // the worker of a version that was since replaced, e.g. by a rollback, is stopped
+        let active_version =
+            call_blocking(&self.module_db, move |db| db.get_active_version(module_id))
+                .await?
+                .ok_or_eyre("module not found")?;
+        let worker = match worker {
+            Some(worker) if worker.version == active_version => worker,
+            _ => {
+                let (module, version) = self.load_module(module_id).await?;
+                if !abi::is_stateful(&module) {
+                    self.stateful_workers.lock().workers.remove(&module_id);
+                    return self.runner.execute(module, input).await;
+                }
+
+                let worker = StatefulWorker {
+                    sender: self.spawn_stateful_worker(module_id, module),
+                    version,
+                };
+                let mut stateful_workers = self.stateful_workers.lock();
+                // the id of a module deleted meanwhile may be reused, its worker must not be found
+                if stateful_workers.evictions == evictions {
+                    stateful_workers.workers.insert(module_id, worker.clone());
+                }
+                worker
+            }
+        };
+
+        // only one task per module runs at a time, so the worker is always ready for it
+        let (result_sender, result) = oneshot::channel();
+        worker
+            .sender
+            .send(StatefulTask { input, result_sender })
+            .await
+            .map_err(|_| eyre!("stateful module worker stopped"))?;
+        result.await?
+    }
+
+    // runs the notifications of a stateful module one after the other on the same instance, until
+    // it has to be recycled
+    fn spawn_stateful_worker(
+        self: &Arc<Self>,
+        module_id: ModuleId,
+        module: Module,
+    ) -> mpsc::Sender<StatefulTask> {
+        let (sender, mut receiver) = mpsc::channel::<StatefulTask>(1);
+        let inner = Arc::clone(self);
+
+        tokio::spawn(async move {
+            let limits = inner.config.stateful;
+            // live instance and the number of notifications it processed
+            let mut instance: Option<(ModuleInstance, u64)> = None;
+
+            while let Some(task) = receiver.recv().await {
+                let res = async {
+                    if instance.is_none() {
+                        instance = Some((inner.runner.instantiate(module.clone()).await?, 0));
+                    }
+                    let (current, calls) = instance.as_mut().expect("instance was just created");
+                    *calls += 1;
+                    current.run(task.input).await
+                }
+                .await;
+
+                let recycle = instance.as_ref().is_some_and(|(current, calls)| {
+                    res.as_ref().is_err_and(is_trap)
+                        || *calls >= limits.max_calls
+                        || current.memory_size() >= limits.max_memory
+                });
+                if recycle {
+                    info!(%module_id, "Recycling stateful module instance.");
+                    instance = None;
+                }
+
+                let _ = task.result_sender.send(res);
+            }
+        });
+
+        sender
+    }
+
+    // get module from db, check it wasn't corrupted, deserialize it and make sure it still
+    // matches the abi, along with the version it was loaded from. Reading the store and
+    // compiling the module both block, so it runs off the async workers
+    async fn load_module(self: &Arc<Self>, module_id: ModuleId) -> Result<(Module, u32)> {
+        let inner = Arc::clone(self);
+        tokio::task::spawn_blocking(move || {
+            let binary = inner.module_db.get_binary(module_id)?.ok_or_eyre("module not found")?;
+            binary.verify()?;
+            let module = Module::from_binary(inner.runner.engine(), &binary.binary)
+                .map_err(|err| AbiError::InvalidModule(err.to_string()))?;
+            abi::validate_module(&module)?;
+            Ok((module, binary.version))
+        })
+        .await?
+    }
+}
+
+fn record_queue_depth(tasks: &TaskQueue<Task>) {
+    metrics::gauge!("xxfunc_runtime_queue_depth").set(tasks.len() as f64);
+}
+
+// an instance can't be reused after a failure other than its handler returning an error
+fn is_trap(err: &eyre::Report) -> bool {
+    !matches!(err.downcast_ref::<ModuleError>(), Some(ModuleError::Returned(_)))
+}
+
+fn serialize_notification(exex_notification: &ExExNotification) -> Vec<u8> {
+    // Can't even do JSON encode of a full struct smh, "key must be a string"
+    serde_json::to_vec(&exex_notification.committed_chain().map(|chain| chain.tip().header.clone()))
+        .unwrap()
+}
+
 #[cfg(test)]
 mod tests {
-    use reth_execution_types::Chain;
-    use xxfunc_db::ModuleState;
+    use xxfunc_db::{MemoryStore, ModuleState};
 
     use super::*;
+    use crate::test_utils::test_notification;
+    use futures::future::join_all;
     use std::sync::Arc;
 
+    // store with the minimal example deployed as `test_module`
+    fn test_store() -> Result<Arc<MemoryStore>> {
+        let store = Arc::new(MemoryStore::new());
+        store.insert(
+            "test_module",
+            include_bytes!("../../examples/minimal/wasm_output/output.wasm"),
+        )?;
+        Ok(store)
+    }
+
     #[tokio::test]
     async fn test_runtime() -> Result<()> {
-        // Create a test database and insert a test module
-        let db = ModuleDatabase::create_test_db()?;
+        // Create a test store and insert a test module
+        let db = test_store()?;
 
         db.set_state("test_module", ModuleState::Started)?;
 
@@ -141,19 +573,11 @@ mod tests {
         // Get the test module ID
         let module_id = db.get_modules_by_state(ModuleState::Started)?[0];
 
-        let notification = ExExNotification::ChainCommitted {
-            new: Arc::new(Chain::from_block(
-                Default::default(),
-                Default::default(),
-                Default::default(),
-            )),
-        };
-
         // Create a mock ExEx notification
-        let exex_notification = Arc::new(notification);
+        let exex_notification = test_notification();
 
         // Spawn a task on the runtime
-        let handle = runtime.spawn(module_id, exex_notification);
+        let handle = runtime.spawn(module_id, ModuleSchedule::default(), exex_notification).await;
 
         // Wait for the task to complete
         let result = handle.await?;
@@ -163,4 +587,277 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_stateful_runtime() -> Result<()> {
+        let db = test_store()?;
+        let bytes = include_bytes!("../../examples/stateful/wasm_output/output.wasm");
+        db.insert("stateful_module", bytes)?;
+        db.set_state("stateful_module", ModuleState::Started)?;
+        let module_id = db.get_modules_by_state(ModuleState::Started)?[0];
+
+        let notification = test_notification();
+
+        // the instance is kept alive and remembers the previous notification
+        let runtime = Runtime::new(db.clone())?;
+        runtime
+            .spawn(module_id, ModuleSchedule::default(), Arc::clone(&notification))
+            .await
+            .await??;
+        let handle =
+            runtime.spawn(module_id, ModuleSchedule::default(), Arc::clone(&notification)).await;
+        assert!(handle.await?.is_err());
+
+        // a new version gets a fresh instance
+        db.insert("stateful_module", bytes)?;
+        runtime
+            .spawn(module_id, ModuleSchedule::default(), Arc::clone(&notification))
+            .await
+            .await??;
+
+        // recycling the instance after each notification drops its state
+        let config = RuntimeConfig {
+            stateful: StatefulConfig { max_calls: 1, ..Default::default() },
+            ..Default::default()
+        };
+        let runtime = Runtime::with_config(db, config)?;
+        runtime
+            .spawn(module_id, ModuleSchedule::default(), Arc::clone(&notification))
+            .await
+            .await??;
+        runtime.spawn(module_id, ModuleSchedule::default(), notification).await.await??;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_evict_drops_stateful_instance() -> Result<()> {
+        let db = Arc::new(MemoryStore::new());
+        db.insert(
+            "stateful_module",
+            include_bytes!("../../examples/stateful/wasm_output/output.wasm"),
+        )?;
+        let module_id = db.get_id("stateful_module")?.unwrap();
+        let notification = test_notification();
+
+        let runtime = Runtime::new(db)?;
+        runtime
+            .spawn(module_id, ModuleSchedule::default(), Arc::clone(&notification))
+            .await
+            .await??;
+        // a fresh instance doesn't remember the notification it would reject as a repeat
+        runtime.evict(module_id);
+        runtime.spawn(module_id, ModuleSchedule::default(), notification).await.await??;
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_every_spawned_task_completes() -> Result<()> {
+        let db = test_store()?;
+        db.set_state("test_module", ModuleState::Started)?;
+        let module_id = db.get_modules_by_state(ModuleState::Started)?[0];
+
+        // a small queue also exercises waiting for room
+        let config = RuntimeConfig { num_workers: 4, queue_capacity: 64, ..Default::default() };
+        let runtime = Runtime::with_config(db, config)?;
+
+        let notification = test_notification();
+
+        // tasks of unknown modules fail right away, so workers keep going idle while new tasks
+        // come in
+        let mut handles = Vec::new();
+        for _ in 0..100 {
+            for unknown_id in 1000..1050 {
+                handles.push(
+                    runtime
+                        .spawn(unknown_id, ModuleSchedule::default(), Arc::clone(&notification))
+                        .await,
+                );
+            }
+            tokio::task::yield_now().await;
+        }
+        let module_handles = [
+            runtime.spawn(module_id, ModuleSchedule::default(), Arc::clone(&notification)).await,
+            runtime.spawn(module_id, ModuleSchedule::default(), notification).await,
+        ];
+
+        let results = tokio::time::timeout(Duration::from_secs(60), join_all(handles)).await?;
+        assert_eq!(results.len(), 5000);
+        for result in results {
+            assert!(result?.is_err());
+        }
+        for result in join_all(module_handles).await {
+            result??;
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_skip_module_when_queue_full() -> Result<()> {
+        let db = test_store()?;
+        db.set_state("test_module", ModuleState::Started)?;
+        let module_id = db.get_modules_by_state(ModuleState::Started)?[0];
+
+        let config = RuntimeConfig {
+            queue_capacity: 0,
+            overflow_policy: OverflowPolicy::SkipModule,
+            ..Default::default()
+        };
+        let runtime = Runtime::with_config(db, config)?;
+
+        let handle = runtime.spawn(module_id, ModuleSchedule::default(), test_notification()).await;
+
+        let err = handle.await?.unwrap_err();
+        assert_eq!(err.downcast_ref::<QueueError>(), Some(&QueueError::Full));
+        assert_eq!(runtime.queue_depth(), 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_drop_oldest_when_queue_full() -> Result<()> {
+        let db = test_store()?;
+        let module_id = db.get_id("test_module")?.expect("module exists");
+        // sleeps for a second, keeping the only worker busy
+        db.insert("async_module", include_bytes!("../../examples/async/wasm_output/output.wasm"))?;
+        let async_module_id = db.get_id("async_module")?.expect("module exists");
+
+        let config = RuntimeConfig {
+            num_workers: 1,
+            queue_capacity: 2,
+            overflow_policy: OverflowPolicy::DropOldest,
+            ..Default::default()
+        };
+        let runtime = Runtime::with_config(db, config)?;
+
+        let notification = test_notification();
+        let running = runtime
+            .spawn(async_module_id, ModuleSchedule::default(), Arc::clone(&notification))
+            .await;
+        while runtime.queue_depth() > 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let mut handles = Vec::new();
+        for _ in 0..3 {
+            handles.push(
+                runtime
+                    .spawn(module_id, ModuleSchedule::default(), Arc::clone(&notification))
+                    .await,
+            );
+        }
+        assert_eq!(runtime.queue_depth(), 2);
+
+        // the oldest task made room for the newest one
+        let mut results = join_all(handles).await.into_iter();
+        let err = results.next().expect("three tasks")?.unwrap_err();
+        assert_eq!(err.downcast_ref::<QueueError>(), Some(&QueueError::Dropped));
+        for result in results {
+            result??;
+        }
+        running.await??;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_executes_queued_tasks() -> Result<()> {
+        let db = test_store()?;
+        db.set_state("test_module", ModuleState::Started)?;
+        let module_id = db.get_modules_by_state(ModuleState::Started)?[0];
+
+        let config = RuntimeConfig { num_workers: 1, ..Default::default() };
+        let runtime = Runtime::with_config(db, config)?;
+
+        let notification = test_notification();
+        let mut handles = Vec::new();
+        for _ in 0..3 {
+            handles.push(
+                runtime
+                    .spawn(module_id, ModuleSchedule::default(), Arc::clone(&notification))
+                    .await,
+            );
+        }
+
+        runtime.shutdown(Instant::now() + Duration::from_secs(30)).await;
+        for result in join_all(handles).await {
+            result??;
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_cancels_tasks_at_deadline() -> Result<()> {
+        let db = test_store()?;
+        db.set_state("test_module", ModuleState::Started)?;
+        let test_module_id = db.get_modules_by_state(ModuleState::Started)?[0];
+        // sleeps for a second
+        db.insert("async_module", include_bytes!("../../examples/async/wasm_output/output.wasm"))?;
+        db.set_state("async_module", ModuleState::Started)?;
+        let async_module_id = db
+            .get_modules_by_state(ModuleState::Started)?
+            .into_iter()
+            .find(|id| *id != test_module_id)
+            .expect("async module is started");
+
+        let config = RuntimeConfig { num_workers: 1, ..Default::default() };
+        let runtime = Runtime::with_config(db, config)?;
+
+        let notification = test_notification();
+        let running = runtime
+            .spawn(async_module_id, ModuleSchedule::default(), Arc::clone(&notification))
+            .await;
+        let queued = runtime.spawn(test_module_id, ModuleSchedule::default(), notification).await;
+
+        runtime.shutdown(Instant::now() + Duration::from_millis(100)).await;
+
+        // the running execution was dropped and the queued one never started
+        assert!(running.await.is_err());
+        let err = queued.await?.unwrap_err();
+        assert_eq!(err.downcast_ref::<QueueError>(), Some(&QueueError::Closed));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_invoke_and_stats() -> Result<()> {
+        let db = test_store()?;
+        let module_id = db.get_id("test_module")?.unwrap();
+        let unknown_id = module_id + 1;
+        let runtime = Runtime::new(db)?;
+
+        let notification = test_notification();
+
+        // the output is captured instead of going to the host's stdout
+        let invocation = runtime.invoke(module_id, &notification).await??;
+        assert_eq!(invocation.error, None);
+        assert!(String::from_utf8(invocation.stdout)?.contains("Hello, world from wasi!"));
+        assert_eq!(runtime.stats(module_id), ModuleStats::default());
+
+        runtime
+            .spawn(module_id, ModuleSchedule::default(), Arc::clone(&notification))
+            .await
+            .await??;
+        let err = runtime
+            .spawn(unknown_id, ModuleSchedule::default(), notification)
+            .await
+            .await?
+            .unwrap_err();
+        assert_eq!(
+            runtime.stats(module_id),
+            ModuleStats { executions: 1, failures: 0, last_error: None }
+        );
+        assert_eq!(
+            runtime.stats(unknown_id),
+            ModuleStats { executions: 1, failures: 1, last_error: Some(err.to_string()) }
+        );
+
+        runtime.evict(unknown_id);
+        assert_eq!(runtime.stats(unknown_id), ModuleStats::default());
+
+        Ok(())
+    }
 }
@@ -0,0 +1,14 @@
+use reth_execution_types::Chain;
+use reth_exex_types::ExExNotification;
+use std::sync::Arc;
+
+// notification committing a chain of a single default block
+pub(crate) fn test_notification() -> Arc<ExExNotification> {
+    Arc::new(ExExNotification::ChainCommitted {
+        new: Arc::new(Chain::from_block(
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        )),
+    })
+}
@@ -1,59 +1,152 @@
-use std::fs::File;
+use std::{fmt, time::Duration};
 
 use eyre::{eyre, ContextCompat, Result};
-use serde_json;
 use wasmtime::{
-    Config, Engine, Instance, Linker, Memory, Module as WasmModule, Store, WasmBacktraceDetails,
+    Caller, Config, Engine, Extern, Instance, Linker, Memory, Module as WasmModule, Store,
+    StoreLimits, StoreLimitsBuilder, Trap, WasmBacktraceDetails,
+};
+use wasmtime_wasi::{pipe::MemoryOutputPipe, preview1};
+
+use crate::abi::{
+    self, AbiError, ABI_VERSION, ABI_VERSION_EXPORT, ALLOC_EXPORT, DEALLOC_EXPORT, MEMORY_EXPORT,
+    PROCESS_EXPORT,
 };
-use wasmtime_wasi::{preview1, DirPerms, FilePerms, OutputFile};
 
 type AllocParams = (u64,);
 type AllocReturn = u64;
+type DeallocParams = (u64, u64);
+type DeallocReturn = ();
 type NotificationParams = (u64, u64);
 type NotificationReturn = u64;
+type AbiVersionParams = ();
+type AbiVersionReturn = u32;
+
+/// Bytes of stdout and stderr captured by [`ModuleRunner::invoke`], each. Writes past it fail
+/// in the module.
+const CAPTURED_OUTPUT_CAPACITY: usize = 1 << 20;
+
+/// Value returned by `process` when the handler succeeded. Any other value locates the error
+/// message in guest memory as `(ptr << 32) | len`.
+const PROCESS_OK: NotificationReturn = 0;
+
+/// Fuel a module consumes between two yields to the executor, so a module that doesn't await
+/// can still be timed out.
+const FUEL_YIELD_INTERVAL: u64 = 100_000;
+
+/// Resources a single execution of a module may use. `None` leaves the resource unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ModuleLimits {
+    /// fuel, roughly the number of wasm instructions, per execution
+    pub fuel: Option<u64>,
+    /// size of the linear memory in bytes
+    pub memory: Option<usize>,
+    /// wall-clock time per execution
+    pub timeout: Option<Duration>,
+}
+
+impl ModuleLimits {
+    // fuel also makes modules yield periodically, for the timeout to interrupt them
+    fn consumes_fuel(&self) -> bool {
+        self.fuel.is_some() || self.timeout.is_some()
+    }
+}
+
+/// Error raised by the guest module itself, as opposed to a failure of the host to run it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModuleError {
+    /// The handler returned an `Err` with the given message.
+    Returned(String),
+    /// The handler panicked, with the message and location reported by the panic hook.
+    Panicked(String),
+    /// The execution used up the configured fuel.
+    OutOfFuel,
+    /// The execution didn't finish within the configured timeout.
+    TimedOut(Duration),
+}
+
+impl fmt::Display for ModuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModuleError::Returned(message) => write!(f, "module returned error: {message}"),
+            ModuleError::Panicked(message) => write!(f, "module {message}"),
+            ModuleError::OutOfFuel => write!(f, "module ran out of fuel"),
+            ModuleError::TimedOut(timeout) => write!(f, "module timed out after {timeout:?}"),
+        }
+    }
+}
+
+impl std::error::Error for ModuleError {}
+
+/// Outcome of a single execution with the output of the module captured.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Invocation {
+    /// error returned or raised by the module, `None` if it succeeded
+    pub error: Option<String>,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+/// State of a single module instance, accessible from host functions.
+struct HostState {
+    wasi: preview1::WasiP1Ctx,
+    /// panic message reported by the guest right before it traps
+    panic: Option<String>,
+    limits: StoreLimits,
+}
 
 pub struct ModuleRunner {
     engine: Engine,
-    linker: Linker<preview1::WasiP1Ctx>,
+    linker: Linker<HostState>,
+    limits: ModuleLimits,
 }
 
 impl ModuleRunner {
     pub fn new() -> Result<Self> {
+        Self::with_limits(ModuleLimits::default())
+    }
+
+    /// Creates a runner whose executions are bounded by the given limits.
+    pub fn with_limits(limits: ModuleLimits) -> Result<Self> {
         // enable async support which requires using the WASI preview1 API
         let mut config = Config::new();
         config.async_support(true);
         config.wasm_backtrace_details(WasmBacktraceDetails::Enable);
+        config.consume_fuel(limits.consumes_fuel());
 
         let engine = wasmtime::Engine::new(&config).map_err(|e| eyre!(e))?;
-        let mut linker = Linker::<preview1::WasiP1Ctx>::new(&engine);
-        preview1::add_to_linker_async(&mut linker, |t| t).map_err(|err| eyre!(err))?;
+        let mut linker = Linker::<HostState>::new(&engine);
+        preview1::add_to_linker_async(&mut linker, |state| &mut state.wasi)
+            .map_err(|err| eyre!(err))?;
+        linker.func_wrap("xxfunc", "panic", report_panic).map_err(|err| eyre!(err))?;
 
-        Ok(Self { engine, linker })
+        Ok(Self { engine, linker, limits })
     }
 
     // TODO: make input the exex notification
     pub async fn execute(&self, module: WasmModule, input: Vec<u8>) -> Result<()> {
-        let mut module = Module::new(self, module).await?;
-        let _ = module.run(input).await?;
-        Ok(())
+        let mut module = self.instantiate(module).await?;
+        module.run(input).await
     }
 
-    pub fn engine(&self) -> &Engine {
-        &self.engine
-    }
-}
+    /// Runs the module once on a fresh instance, capturing what it writes to stdout and stderr
+    /// instead of inheriting the host's.
+    pub async fn invoke(&self, module: WasmModule, input: Vec<u8>) -> Result<Invocation> {
+        let stdout = MemoryOutputPipe::new(CAPTURED_OUTPUT_CAPACITY);
+        let stderr = MemoryOutputPipe::new(CAPTURED_OUTPUT_CAPACITY);
+        let ctx = wasmtime_wasi::WasiCtxBuilder::new()
+            .stdout(stdout.clone())
+            .stderr(stderr.clone())
+            .build_p1();
 
-struct Module {
-    memory: Memory,
-    instance: Instance,
-    store: Store<preview1::WasiP1Ctx>,
-}
+        let mut instance = ModuleInstance::new(self, module, ctx).await?;
+        let error = instance.run(input).await.err().map(|err| err.to_string());
 
-impl Module {
-    async fn new(runner: &ModuleRunner, module: WasmModule) -> Result<Self> {
-        // let output_file = File::create("wasm_stdout.log")?;
-        // let stdout = OutputFile::new(output_file);
+        Ok(Invocation { error, stdout: stdout.contents().into(), stderr: stderr.contents().into() })
+    }
 
+    /// Creates an instance of the module that can process several inputs in a row, keeping its
+    /// memory between them.
+    pub async fn instantiate(&self, module: WasmModule) -> Result<ModuleInstance> {
         // setup the WASI context, with file access to the reth data directory
         let ctx = wasmtime_wasi::WasiCtxBuilder::new()
             .inherit_stdio()
@@ -61,7 +154,80 @@ impl Module {
             // .expect("failed to preopened dir")
             .build_p1();
 
-        let mut store = Store::new(&runner.engine, ctx);
+        ModuleInstance::new(self, module, ctx).await
+    }
+
+    /// Compiles the binary and checks that it implements the ABI expected by the runtime,
+    /// including the version it reports once instantiated. Instantiating and asking for the
+    /// version are bounded by the same limits as an execution.
+    pub async fn validate(&self, bytes: &[u8]) -> Result<WasmModule> {
+        // compiling is CPU-bound, keep it off the async workers
+        let (engine, bytes) = (self.engine.clone(), bytes.to_vec());
+        let module = tokio::task::spawn_blocking(move || {
+            let module = WasmModule::from_binary(&engine, &bytes)
+                .map_err(|err| AbiError::InvalidModule(err.to_string()))?;
+            abi::validate_module(&module)?;
+            Ok::<_, AbiError>(module)
+        })
+        .await??;
+
+        let abi_version = async {
+            // the start function runs on instantiation, under the fuel and memory limits
+            let mut instance = self.instantiate(module.clone()).await?;
+            instance.abi_version().await
+        };
+        let version = match self.limits.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, abi_version)
+                .await
+                .map_err(|_| ModuleError::TimedOut(timeout))??,
+            None => abi_version.await?,
+        };
+        if version != ABI_VERSION {
+            return Err(
+                AbiError::UnsupportedVersion { expected: ABI_VERSION, found: version }.into()
+            );
+        }
+
+        Ok(module)
+    }
+
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    pub fn limits(&self) -> ModuleLimits {
+        self.limits
+    }
+}
+
+/// Instantiated module, with its own store and memory.
+pub struct ModuleInstance {
+    memory: Memory,
+    instance: Instance,
+    store: Store<HostState>,
+    /// guest buffers owned by the host, as `(ptr, size)`, freed once `process` returns
+    allocations: Vec<(u64, u64)>,
+    limits: ModuleLimits,
+}
+
+impl ModuleInstance {
+    async fn new(
+        runner: &ModuleRunner,
+        module: WasmModule,
+        ctx: preview1::WasiP1Ctx,
+    ) -> Result<Self> {
+        let limits = runner.limits;
+        let mut store_limits = StoreLimitsBuilder::new();
+        if let Some(memory) = limits.memory {
+            store_limits = store_limits.memory_size(memory);
+        }
+        let state = HostState { wasi: ctx, panic: None, limits: store_limits.build() };
+        let mut store = Store::new(&runner.engine, state);
+        store.limiter(|state| &mut state.limits);
+        if limits.consumes_fuel() {
+            store.set_fuel(limits.fuel.unwrap_or(u64::MAX)).map_err(|err| eyre!(err))?;
+            store.fuel_async_yield_interval(Some(FUEL_YIELD_INTERVAL)).map_err(|err| eyre!(err))?;
+        }
 
         let instance = runner
             .linker
@@ -69,12 +235,29 @@ impl Module {
             .await
             .map_err(|err| eyre!("failed to instantiate: {err}"))?;
 
-        let memory = instance.get_memory(&mut store, "memory").context("failed to get memory")?;
+        let memory =
+            instance.get_memory(&mut store, MEMORY_EXPORT).context("failed to get memory")?;
 
-        Ok(Self { store, instance, memory })
+        Ok(Self { store, instance, memory, allocations: Vec::new(), limits })
     }
 
-    async fn run(&mut self, input: Vec<u8>) -> Result<()> {
+    /// Hands the input to the module's `process` export. Buffers allocated for the call are
+    /// freed before returning, unless the instance trapped or timed out.
+    pub async fn run(&mut self, input: Vec<u8>) -> Result<()> {
+        // every execution gets the full fuel, whatever the previous ones used
+        if let Some(fuel) = self.limits.fuel {
+            self.store.set_fuel(fuel).map_err(|err| eyre!(err))?;
+        }
+
+        match self.limits.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, self.run_unbounded(input))
+                .await
+                .map_err(|_| ModuleError::TimedOut(timeout))?,
+            None => self.run_unbounded(input).await,
+        }
+    }
+
+    async fn run_unbounded(&mut self, input: Vec<u8>) -> Result<()> {
         // Allocate memory for the notification.
         let data_size = input.len() as u64;
         let ptr = self.alloc(data_size).await?;
@@ -82,10 +265,34 @@ impl Module {
         // Write the notification to the allocated memory.
         self.write(ptr as usize, &input)?;
 
-        // Call the notification function that will read the allocated memory.
-        let _ = self.process(ptr, data_size).await?;
+        // Call the notification function that will read the allocated memory. A trapped instance
+        // can't be called into anymore, so its buffers are left to be dropped with it.
+        let result = self.process(ptr, data_size).await?;
+        let output = if result == PROCESS_OK {
+            Ok(())
+        } else {
+            let (ptr, len) = (result >> 32, result & u64::from(u32::MAX));
+            self.allocations.push((ptr, len));
+            // the message is freed with the input even if it can't be read
+            self.read(ptr as usize, len as usize).and_then(|message| {
+                Err(ModuleError::Returned(String::from_utf8_lossy(&message).into()).into())
+            })
+        };
 
-        Ok(())
+        self.free_allocations().await?;
+        output
+    }
+
+    /// Current size of the instance's linear memory in bytes.
+    pub fn memory_size(&self) -> usize {
+        self.memory.data_size(&self.store)
+    }
+
+    // read `len` bytes from the memory at the given pointer.
+    fn read(&self, ptr: usize, len: usize) -> Result<Vec<u8>> {
+        let mut buffer = vec![0; len];
+        self.memory.read(&self.store, ptr, &mut buffer)?;
+        Ok(buffer)
     }
 
     // write the buffer to the memory at the given pointer.
@@ -94,32 +301,122 @@ impl Module {
         Ok(())
     }
 
-    // allocate `size` amount of memory and return the pointer to the allocated memory.
+    // allocate `size` amount of memory and return the pointer to the allocated memory. The buffer
+    // is tracked until `free_allocations` is called.
     async fn alloc(&mut self, size: u64) -> Result<u64> {
         let func = self
             .instance
-            .get_typed_func::<AllocParams, AllocReturn>(&mut self.store, "alloc")
+            .get_typed_func::<AllocParams, AllocReturn>(&mut self.store, ALLOC_EXPORT)
             .map_err(|err| eyre!("failed to get alloc func: {err}"))?;
 
         let ptr = func
             .call_async(&mut self.store, (size,))
             .await
-            .map_err(|err| eyre::eyre!("failed to call alloc func: {err}"))?;
+            .map_err(|err| call_error("failed to call alloc func", err))?;
+        if ptr == 0 {
+            return Err(eyre!("module failed to allocate {size} bytes"));
+        }
 
+        self.allocations.push((ptr, size));
         Ok(ptr)
     }
 
+    // free every buffer handed out to the host since the last call.
+    async fn free_allocations(&mut self) -> Result<()> {
+        let func = self
+            .instance
+            .get_typed_func::<DeallocParams, DeallocReturn>(&mut self.store, DEALLOC_EXPORT)
+            .map_err(|err| eyre!("failed to get dealloc func: {err}"))?;
+
+        for (ptr, size) in std::mem::take(&mut self.allocations) {
+            func.call_async(&mut self.store, (ptr, size))
+                .await
+                .map_err(|err| call_error("failed to call dealloc func", err))?;
+        }
+
+        Ok(())
+    }
+
+    // version of the guest ABI the module was built against.
+    async fn abi_version(&mut self) -> Result<u32> {
+        let func = self
+            .instance
+            .get_typed_func::<AbiVersionParams, AbiVersionReturn>(
+                &mut self.store,
+                ABI_VERSION_EXPORT,
+            )
+            .map_err(|err| eyre!("failed to get abi version func: {err}"))?;
+
+        func.call_async(&mut self.store, ())
+            .await
+            .map_err(|err| call_error("failed to call abi version func", err))
+    }
+
     async fn process(&mut self, ptr: u64, size: u64) -> Result<u64> {
         let func = self
             .instance
-            .get_typed_func::<NotificationParams, NotificationReturn>(&mut self.store, "process")
+            .get_typed_func::<NotificationParams, NotificationReturn>(
+                &mut self.store,
+                PROCESS_EXPORT,
+            )
             .map_err(|err| eyre!("failed to get process func: {err}"))?;
 
-        let result = func
-            .call_async(&mut self.store, (ptr, size))
-            .await
-            .map_err(|err| eyre::eyre!("failed to call process func: {err}"))?;
+        let result = func.call_async(&mut self.store, (ptr, size)).await.map_err(|err| {
+            // a trap preceded by a reported panic is the guest panicking
+            match self.store.data_mut().panic.take() {
+                Some(message) => ModuleError::Panicked(message).into(),
+                None => call_error("failed to call process func", err),
+            }
+        })?;
 
         Ok(result)
     }
 }
+
+// error of a call into the guest, telling apart running out of fuel from other traps
+fn call_error(context: &str, err: wasmtime::Error) -> eyre::Report {
+    match err.downcast_ref::<Trap>() {
+        Some(Trap::OutOfFuel) => ModuleError::OutOfFuel.into(),
+        _ => eyre!("{context}: {err}"),
+    }
+}
+
+// host function called by the guest panic hook with the formatted panic message
+fn report_panic(mut caller: Caller<'_, HostState>, ptr: u64, len: u64) -> wasmtime::Result<()> {
+    let memory = caller
+        .get_export(MEMORY_EXPORT)
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| wasmtime::Error::msg("failed to get memory"))?;
+
+    let mut buffer = vec![0; len as usize];
+    memory.read(&caller, ptr as usize, &mut buffer)?;
+    caller.data_mut().panic = Some(String::from_utf8_lossy(&buffer).into());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_reused_module_frees_buffers() -> Result<()> {
+        let runner = ModuleRunner::new()?;
+        let bytes = include_bytes!("../../examples/fallible/wasm_output/output.wasm");
+        let module = WasmModule::from_binary(runner.engine(), bytes).map_err(|err| eyre!(err))?;
+        let mut module = runner.instantiate(module).await?;
+
+        let input = vec![1; 1 << 20];
+        module.run(input.clone()).await?;
+        let memory_size = module.memory_size();
+
+        // without freeing, every run would grow the memory by the size of the input
+        for _ in 0..16 {
+            module.run(input.clone()).await?;
+            assert!(module.run(Vec::new()).await.is_err());
+            assert!(module.allocations.is_empty());
+        }
+        assert_eq!(module.memory_size(), memory_size);
+
+        Ok(())
+    }
+}
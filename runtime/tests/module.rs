@@ -1,6 +1,11 @@
+use std::time::Duration;
+
 use eyre::Result;
 use wasmtime::{Engine, Module};
-use xxfunc_runtime::wasm::ModuleRunner;
+use xxfunc_runtime::{
+    abi::{self, AbiError},
+    wasm::{ModuleError, ModuleLimits, ModuleRunner},
+};
 
 fn get_test_minimal_module(engine: &Engine) -> Module {
     let bytes = include_bytes!("../../examples/minimal/wasm_output/output.wasm");
@@ -12,6 +17,16 @@ fn get_test_async_module(engine: &Engine) -> Module {
     Module::from_binary(engine, bytes).unwrap()
 }
 
+fn get_test_fallible_module(engine: &Engine) -> Module {
+    let bytes = include_bytes!("../../examples/fallible/wasm_output/output.wasm");
+    Module::from_binary(engine, bytes).unwrap()
+}
+
+fn get_test_panicking_module(engine: &Engine) -> Module {
+    let bytes = include_bytes!("../../examples/panicking/wasm_output/output.wasm");
+    Module::from_binary(engine, bytes).unwrap()
+}
+
 #[tokio::test]
 async fn run_module() -> Result<()> {
     let runner = ModuleRunner::new()?;
@@ -27,3 +42,134 @@ async fn run_async_module() -> Result<()> {
     runner.execute(module, Vec::new()).await?;
     Ok(())
 }
+
+#[tokio::test]
+async fn run_fallible_module() -> Result<()> {
+    let runner = ModuleRunner::new()?;
+
+    let module = get_test_fallible_module(runner.engine());
+    runner.execute(module, b"notification".to_vec()).await?;
+
+    let module = get_test_fallible_module(runner.engine());
+    let err = runner.execute(module, Vec::new()).await.unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<ModuleError>(),
+        Some(&ModuleError::Returned("exex notification data is empty".to_string()))
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn run_panicking_module() -> Result<()> {
+    let runner = ModuleRunner::new()?;
+
+    let module = get_test_panicking_module(runner.engine());
+    runner.execute(module, b"notification".to_vec()).await?;
+
+    let module = get_test_panicking_module(runner.engine());
+    let err = runner.execute(module, Vec::new()).await.unwrap_err();
+    let Some(ModuleError::Panicked(message)) = err.downcast_ref::<ModuleError>() else {
+        panic!("expected module panic, got: {err}");
+    };
+    assert!(message.contains("exex notification data is empty"));
+    assert!(message.contains("src/main.rs"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn run_module_with_limits() -> Result<()> {
+    let runner = ModuleRunner::with_limits(ModuleLimits { fuel: Some(1), ..Default::default() })?;
+    let module = get_test_minimal_module(runner.engine());
+    let err = runner.execute(module, Vec::new()).await.unwrap_err();
+    assert_eq!(err.downcast_ref::<ModuleError>(), Some(&ModuleError::OutOfFuel));
+
+    // a single page isn't enough for the module's stack
+    let runner =
+        ModuleRunner::with_limits(ModuleLimits { memory: Some(1 << 16), ..Default::default() })?;
+    let module = get_test_minimal_module(runner.engine());
+    assert!(runner.execute(module, Vec::new()).await.is_err());
+
+    // the module sleeps for a second
+    let timeout = Duration::from_millis(100);
+    let runner =
+        ModuleRunner::with_limits(ModuleLimits { timeout: Some(timeout), ..Default::default() })?;
+    let module = get_test_async_module(runner.engine());
+    let err = runner.execute(module, Vec::new()).await.unwrap_err();
+    assert_eq!(err.downcast_ref::<ModuleError>(), Some(&ModuleError::TimedOut(timeout)));
+
+    // limits are generous enough for regular modules
+    let runner = ModuleRunner::with_limits(ModuleLimits {
+        fuel: Some(1_000_000_000),
+        memory: Some(64 << 20),
+        timeout: Some(Duration::from_secs(10)),
+    })?;
+    let module = get_test_minimal_module(runner.engine());
+    runner.execute(module, Vec::new()).await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn validate_modules() -> Result<()> {
+    let runner = ModuleRunner::new()?;
+
+    for bytes in [
+        &include_bytes!("../../examples/minimal/wasm_output/output.wasm")[..],
+        &include_bytes!("../../examples/async/wasm_output/output.wasm")[..],
+    ] {
+        runner.validate(bytes).await?;
+    }
+
+    let err = runner.validate(b"not a wasm module").await.unwrap_err();
+    assert!(matches!(err.downcast_ref::<AbiError>(), Some(AbiError::InvalidModule(_))));
+
+    let module = Module::new(
+        runner.engine(),
+        r#"(module
+            (memory (export "memory") 1)
+            (func (export "alloc") (param i64) (result i64) i64.const 0))"#,
+    )
+    .unwrap();
+    assert_eq!(abi::validate_module(&module), Err(AbiError::MissingExport("dealloc")));
+
+    let module = Module::new(
+        runner.engine(),
+        r#"(module
+            (import "env" "abort" (func))
+            (memory (export "memory") 1))"#,
+    )
+    .unwrap();
+    assert_eq!(
+        abi::validate_module(&module),
+        Err(AbiError::DisallowedImport { module: "env".to_string(), name: "abort".to_string() })
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn validate_bounded_by_limits() -> Result<()> {
+    // implements the ABI, but never returns its version
+    let bytes = wat::parse_str(
+        r#"(module
+            (memory (export "memory") 1)
+            (func (export "alloc") (param i64) (result i64) i64.const 0)
+            (func (export "dealloc") (param i64 i64))
+            (func (export "process") (param i64 i64) (result i64) i64.const 0)
+            (func (export "xxfunc_abi_version") (result i32) (loop (br 0)) i32.const 1))"#,
+    )?;
+
+    let runner = ModuleRunner::with_limits(ModuleLimits {
+        timeout: Some(Duration::from_millis(100)),
+        ..Default::default()
+    })?;
+    let err = runner.validate(&bytes).await.unwrap_err();
+    assert_eq!(err.downcast_ref(), Some(&ModuleError::TimedOut(Duration::from_millis(100))));
+
+    let runner =
+        ModuleRunner::with_limits(ModuleLimits { fuel: Some(1_000_000), ..Default::default() })?;
+    let err = runner.validate(&bytes).await.unwrap_err();
+    assert_eq!(err.downcast_ref(), Some(&ModuleError::OutOfFuel));
+
+    Ok(())
+}
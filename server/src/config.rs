@@ -0,0 +1,176 @@
+//! Server settings, read from a TOML file and overridden from the command line.
+
+use std::{
+    fs,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+};
+
+use clap::Parser;
+use eyre::{Result, WrapErr};
+use serde::Deserialize;
+
+/// Largest request body accepted by default, enough for most modules.
+const DEFAULT_BODY_LIMIT: usize = 16 << 20;
+
+#[derive(Debug, Parser)]
+#[clap(name = "xxfunc-server", about = "HTTP API to deploy, start and stop xxfunc modules")]
+pub struct Args {
+    /// TOML file to read the settings from, overridden by the other arguments
+    #[clap(long, short)]
+    pub config: Option<PathBuf>,
+
+    /// Address to listen on
+    #[clap(long)]
+    pub bind_address: Option<IpAddr>,
+
+    /// Port to listen on
+    #[clap(long)]
+    pub port: Option<u16>,
+
+    /// Path of the module database
+    #[clap(long)]
+    pub db: Option<PathBuf>,
+
+    /// Largest request body accepted, in bytes
+    #[clap(long)]
+    pub body_limit: Option<usize>,
+
+    /// Log filter, e.g. `info` or `xxfunc_server=debug`, overriding `RUST_LOG`
+    #[clap(long)]
+    pub log_level: Option<String>,
+
+    /// PEM certificate chain to serve HTTPS with, along with `--tls-key`
+    #[clap(long, requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key of the certificate
+    #[clap(long, requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind_address: IpAddr,
+    pub port: u16,
+    pub db: PathBuf,
+    /// largest request body accepted, in bytes
+    pub body_limit: usize,
+    /// log filter, `RUST_LOG` or `info` if unset
+    pub log_level: Option<String>,
+    /// serves HTTPS instead of HTTP when set
+    pub tls: Option<TlsConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    /// PEM certificate chain
+    pub cert: PathBuf,
+    /// PEM private key
+    pub key: PathBuf,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind_address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port: 3000,
+            db: PathBuf::from("module.db"),
+            body_limit: DEFAULT_BODY_LIMIT,
+            log_level: None,
+            tls: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Reads the config file if any, then applies the command line arguments on top.
+    pub fn load(args: Args) -> Result<Self> {
+        let mut config = match &args.config {
+            Some(path) => Self::from_file(path)?,
+            None => Self::default(),
+        };
+
+        if let Some(bind_address) = args.bind_address {
+            config.bind_address = bind_address;
+        }
+        if let Some(port) = args.port {
+            config.port = port;
+        }
+        if let Some(db) = args.db {
+            config.db = db;
+        }
+        if let Some(body_limit) = args.body_limit {
+            config.body_limit = body_limit;
+        }
+        if args.log_level.is_some() {
+            config.log_level = args.log_level;
+        }
+        if let (Some(cert), Some(key)) = (args.tls_cert, args.tls_key) {
+            config.tls = Some(TlsConfig { cert, key });
+        }
+
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .wrap_err_with(|| format!("failed to read config {}", path.display()))?;
+        toml::from_str(&content).wrap_err_with(|| format!("invalid config {}", path.display()))
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        SocketAddr::new(self.bind_address, self.port)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_args_override_file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("server.toml");
+        fs::write(
+            &path,
+            r#"
+            bind_address = "127.0.0.1"
+            port = 4000
+            db = "/var/lib/xxfunc/module.db"
+
+            [tls]
+            cert = "cert.pem"
+            key = "key.pem"
+            "#,
+        )?;
+
+        let args = Args::parse_from(["xxfunc-server", "--config", path.to_str().unwrap()]);
+        let config = ServerConfig::load(args)?;
+        assert_eq!(config.addr(), "127.0.0.1:4000".parse()?);
+        assert_eq!(config.db, PathBuf::from("/var/lib/xxfunc/module.db"));
+        assert_eq!(config.body_limit, DEFAULT_BODY_LIMIT);
+        assert_eq!(config.tls, Some(TlsConfig { cert: "cert.pem".into(), key: "key.pem".into() }));
+
+        let args = Args::parse_from([
+            "xxfunc-server",
+            "--config",
+            path.to_str().unwrap(),
+            "--port",
+            "5000",
+            "--body-limit",
+            "1024",
+        ]);
+        let config = ServerConfig::load(args)?;
+        assert_eq!(config.addr(), "127.0.0.1:5000".parse()?);
+        assert_eq!(config.body_limit, 1024);
+
+        fs::write(&path, "prot = 4000")?;
+        let args = Args::parse_from(["xxfunc-server", "--config", path.to_str().unwrap()]);
+        assert!(ServerConfig::load(args).is_err());
+
+        Ok(())
+    }
+}
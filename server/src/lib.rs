@@ -0,0 +1,581 @@
+//! HTTP API to deploy, start, stop and describe modules.
+
+pub mod config;
+pub mod tls;
+
+use axum::{
+    extract::{DefaultBodyLimit, Json, Multipart},
+    http::StatusCode,
+    routing::{get, post},
+    Router,
+};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::{sync::Arc, time::Duration};
+use tracing::{error, info};
+use xxfunc_db::{
+    call_blocking, MetadataUpdate, ModuleMetadata, ModuleSchedule, ModuleState, ModuleStore,
+    ModuleVersion, Priority, StoreError,
+};
+use xxfunc_runtime::{abi::ABI_VERSION, wasm::ModuleRunner};
+
+// response to a failed store call
+fn store_error(err: StoreError) -> (StatusCode, String) {
+    let status = match err {
+        StoreError::NotFound | StoreError::VersionNotFound => StatusCode::NOT_FOUND,
+        StoreError::NoEarlierVersion => StatusCode::CONFLICT,
+        StoreError::InvalidWeight => StatusCode::BAD_REQUEST,
+        StoreError::Backend(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    (status, err.to_string())
+}
+
+/// Version created by `/deploy`, the hash letting clients check what the node runs.
+#[derive(Serialize)]
+struct DeployedModule {
+    module: String,
+    version: u32,
+    /// hex encoded SHA-256 of the wasm binary
+    hash: String,
+}
+
+async fn deploy(
+    mut multipart: Multipart,
+    module_db: Arc<dyn ModuleStore>,
+    runner: Arc<ModuleRunner>,
+) -> Result<Json<DeployedModule>, (StatusCode, String)> {
+    let mut file_name = String::new();
+    let mut binary = None;
+    let mut deployer = None;
+
+    while let Some(field) =
+        multipart.next_field().await.map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?
+    {
+        let name = field.name().unwrap_or_default().to_string();
+        if name == "module" {
+            file_name = field.file_name().map(|f| f.to_string()).unwrap_or_default();
+            info!("Received file: {}", file_name);
+            binary =
+                Some(field.bytes().await.map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?);
+        } else if name == "deployer" {
+            deployer =
+                Some(field.text().await.map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?);
+        }
+    }
+
+    let Some(raw_data) = binary.filter(|_| !file_name.is_empty()) else {
+        return Err((StatusCode::BAD_REQUEST, "missing `module` field".to_string()));
+    };
+
+    // reject modules the runtime wouldn't be able to run
+    runner.validate(&raw_data).await.map_err(|e| {
+        error!("Module '{}' rejected: {}", file_name, e);
+        (StatusCode::UNPROCESSABLE_ENTITY, format!("invalid module: {e}"))
+    })?;
+
+    // deploying an existing module adds a version and activates it
+    let name = file_name.clone();
+    let version = call_blocking(&module_db, move |db| {
+        db.deploy(&name, &raw_data, deployer.as_deref(), Some(ABI_VERSION))
+    })
+    .await
+    .map_err(store_error)?;
+    info!("Module '{}' version {} inserted into database", file_name, version.version);
+
+    Ok(Json(DeployedModule { module: file_name, version: version.version, hash: version.hash }))
+}
+
+#[derive(Deserialize)]
+struct ModuleInfo {
+    module: String,
+}
+
+#[derive(Deserialize)]
+struct StartModule {
+    module: String,
+    /// block to process history from, instead of resuming from the last processed block
+    #[serde(default)]
+    start_block: Option<u64>,
+}
+
+async fn start(
+    Json(info): Json<StartModule>,
+    module_db: Arc<dyn ModuleStore>,
+) -> Result<String, (StatusCode, String)> {
+    info!("Starting module: {}", info.module);
+    let module = info.module.clone();
+    call_blocking(&module_db, move |db| {
+        if let Some(start_block) = info.start_block {
+            db.set_start_block(&module, Some(start_block))?;
+        }
+        db.set_state(&module, ModuleState::Started)
+    })
+    .await
+    .map_err(|e| {
+        error!("Failed to start module: {}", e);
+        store_error(e)
+    })?;
+    info!("Module '{}' started successfully", info.module);
+    Ok(info.module)
+}
+
+async fn stop(
+    Json(info): Json<ModuleInfo>,
+    module_db: Arc<dyn ModuleStore>,
+) -> Result<String, (StatusCode, String)> {
+    info!("Stopping module: {}", info.module);
+    let module = info.module.clone();
+    call_blocking(&module_db, move |db| db.set_state(&module, ModuleState::Stopped))
+        .await
+        .map_err(|e| {
+            error!("Failed to set module state: {}", e);
+            store_error(e)
+        })?;
+    info!("Module '{}' stopped successfully", info.module);
+    Ok(info.module)
+}
+
+#[derive(Deserialize)]
+struct SetSchedule {
+    module: String,
+    /// `High`, `Normal` or `Low`
+    priority: String,
+    /// share of execution time relative to the other modules of the class, at least 1
+    weight: u32,
+}
+
+async fn set_schedule(
+    Json(info): Json<SetSchedule>,
+    module_db: Arc<dyn ModuleStore>,
+) -> Result<String, (StatusCode, String)> {
+    info!("Scheduling module '{}' as {} with weight {}", info.module, info.priority, info.weight);
+    let priority =
+        info.priority.parse::<Priority>().map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    let schedule = ModuleSchedule { priority, weight: info.weight };
+    let module = info.module.clone();
+    call_blocking(&module_db, move |db| db.set_schedule(&module, schedule)).await.map_err(|e| {
+        error!("Failed to set module schedule: {}", e);
+        store_error(e)
+    })?;
+    Ok(info.module)
+}
+
+/// Version of a module, as returned by `/versions`.
+#[derive(Serialize)]
+struct VersionSummary {
+    version: u32,
+    hash: String,
+    size: usize,
+    deployed_at: u64,
+    deployer: Option<String>,
+    abi_version: Option<u32>,
+    active: bool,
+}
+
+impl From<ModuleVersion> for VersionSummary {
+    fn from(version: ModuleVersion) -> Self {
+        Self {
+            version: version.version,
+            hash: version.hash,
+            size: version.size,
+            deployed_at: version.deployed_at,
+            deployer: version.deployer,
+            abi_version: version.abi_version,
+            active: version.active,
+        }
+    }
+}
+
+async fn versions(
+    Json(info): Json<ModuleInfo>,
+    module_db: Arc<dyn ModuleStore>,
+) -> Result<Json<Vec<VersionSummary>>, (StatusCode, String)> {
+    let versions = call_blocking(&module_db, move |db| db.list_versions(&info.module))
+        .await
+        .map_err(store_error)?;
+    Ok(Json(versions.into_iter().map(Into::into).collect()))
+}
+
+#[derive(Deserialize)]
+struct PromoteModule {
+    module: String,
+    version: u32,
+}
+
+async fn promote(
+    Json(info): Json<PromoteModule>,
+    module_db: Arc<dyn ModuleStore>,
+) -> Result<String, (StatusCode, String)> {
+    info!("Promoting module '{}' to version {}", info.module, info.version);
+    let (module, version) = (info.module.clone(), info.version);
+    call_blocking(&module_db, move |db| db.promote(&module, version)).await.map_err(|e| {
+        error!("Failed to promote module: {}", e);
+        store_error(e)
+    })?;
+    Ok(format!("{} version {}", info.module, info.version))
+}
+
+async fn rollback(
+    Json(info): Json<ModuleInfo>,
+    module_db: Arc<dyn ModuleStore>,
+) -> Result<String, (StatusCode, String)> {
+    info!("Rolling back module: {}", info.module);
+    let module = info.module.clone();
+    let version = call_blocking(&module_db, move |db| db.rollback(&module)).await.map_err(|e| {
+        error!("Failed to roll back module: {}", e);
+        store_error(e)
+    })?;
+    info!("Module '{}' rolled back to version {}", info.module, version);
+    Ok(format!("{} version {}", info.module, version))
+}
+
+/// Module with its metadata, as returned by `/list` and `/info`.
+#[derive(Serialize)]
+struct ModuleDetails {
+    name: String,
+    state: String,
+    version: u32,
+    hash: String,
+    size: usize,
+    abi_version: Option<u32>,
+    created_at: u64,
+    updated_at: u64,
+    #[serde(flatten)]
+    metadata: Metadata,
+}
+
+impl From<xxfunc_db::ModuleInfo> for ModuleDetails {
+    fn from(info: xxfunc_db::ModuleInfo) -> Self {
+        Self {
+            name: info.name,
+            state: info.state.to_string(),
+            version: info.version,
+            hash: info.hash,
+            size: info.size,
+            abi_version: info.abi_version,
+            created_at: info.created_at,
+            updated_at: info.updated_at,
+            metadata: info.metadata.into(),
+        }
+    }
+}
+
+/// Metadata of a module, as returned by `/info`.
+#[derive(Serialize)]
+struct Metadata {
+    owner: Option<String>,
+    description: Option<String>,
+    filters: Vec<String>,
+    tags: Vec<String>,
+    fuel: Option<u64>,
+    /// bytes of linear memory
+    memory: Option<u64>,
+    timeout_ms: Option<u64>,
+}
+
+impl From<ModuleMetadata> for Metadata {
+    fn from(metadata: ModuleMetadata) -> Self {
+        Self {
+            owner: metadata.owner,
+            description: metadata.description,
+            filters: metadata.filters,
+            tags: metadata.tags,
+            fuel: metadata.limits.fuel,
+            memory: metadata.limits.memory,
+            timeout_ms: metadata.limits.timeout.map(|timeout| timeout.as_millis() as u64),
+        }
+    }
+}
+
+/// Fields of the metadata changed by `/metadata`, the ones left out are kept and null ones
+/// cleared.
+#[derive(Deserialize)]
+struct MetadataFields {
+    #[serde(default, deserialize_with = "nullable")]
+    owner: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    description: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    filters: Option<Option<Vec<String>>>,
+    #[serde(default, deserialize_with = "nullable")]
+    tags: Option<Option<Vec<String>>>,
+    #[serde(default, deserialize_with = "nullable")]
+    fuel: Option<Option<u64>>,
+    /// bytes of linear memory
+    #[serde(default, deserialize_with = "nullable")]
+    memory: Option<Option<u64>>,
+    #[serde(default, deserialize_with = "nullable")]
+    timeout_ms: Option<Option<u64>>,
+}
+
+// tells a field set to null, `Some(None)`, apart from a missing one, `None` by default
+fn nullable<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::deserialize(deserializer).map(Some)
+}
+
+impl From<MetadataFields> for MetadataUpdate {
+    fn from(fields: MetadataFields) -> Self {
+        Self {
+            owner: fields.owner,
+            description: fields.description,
+            filters: fields.filters.map(Option::unwrap_or_default),
+            fuel: fields.fuel,
+            memory: fields.memory,
+            timeout: fields.timeout_ms.map(|timeout_ms| timeout_ms.map(Duration::from_millis)),
+            tags: fields.tags.map(Option::unwrap_or_default),
+        }
+    }
+}
+
+async fn list(
+    module_db: Arc<dyn ModuleStore>,
+) -> Result<Json<Vec<ModuleDetails>>, (StatusCode, String)> {
+    let modules = call_blocking(&module_db, |db| db.list()).await.map_err(store_error)?;
+    Ok(Json(modules.into_iter().map(Into::into).collect()))
+}
+
+async fn info(
+    Json(info): Json<ModuleInfo>,
+    module_db: Arc<dyn ModuleStore>,
+) -> Result<Json<ModuleDetails>, (StatusCode, String)> {
+    let module =
+        call_blocking(&module_db, move |db| db.get_info(&info.module)?.ok_or(StoreError::NotFound))
+            .await
+            .map_err(store_error)?;
+    Ok(Json(module.into()))
+}
+
+#[derive(Deserialize)]
+struct SetMetadata {
+    module: String,
+    #[serde(flatten)]
+    fields: MetadataFields,
+}
+
+async fn set_metadata(
+    Json(info): Json<SetMetadata>,
+    module_db: Arc<dyn ModuleStore>,
+) -> Result<String, (StatusCode, String)> {
+    info!("Setting metadata of module: {}", info.module);
+    let SetMetadata { module, fields } = info;
+    let name = module.clone();
+    call_blocking(&module_db, move |db| db.update_metadata(&name, fields.into())).await.map_err(
+        |e| {
+            error!("Failed to set module metadata: {}", e);
+            store_error(e)
+        },
+    )?;
+    Ok(module)
+}
+
+/// Routes of the API, backed by the module store, accepting request bodies up to
+/// `body_limit` bytes.
+pub fn router(
+    module_db: Arc<dyn ModuleStore>,
+    runner: Arc<ModuleRunner>,
+    body_limit: usize,
+) -> Router {
+    Router::new()
+        .route(
+            "/deploy",
+            post({
+                let module_db = Arc::clone(&module_db);
+                let runner = Arc::clone(&runner);
+                move |multipart| deploy(multipart, module_db, runner)
+            }),
+        )
+        .route(
+            "/start",
+            post({
+                let module_db = Arc::clone(&module_db);
+                move |info| start(info, module_db)
+            }),
+        )
+        .route(
+            "/stop",
+            post({
+                let module_db = Arc::clone(&module_db);
+                move |info| stop(info, module_db)
+            }),
+        )
+        .route(
+            "/list",
+            get({
+                let module_db = Arc::clone(&module_db);
+                move || list(module_db)
+            }),
+        )
+        .route(
+            "/info",
+            post({
+                let module_db = Arc::clone(&module_db);
+                move |body| info(body, module_db)
+            }),
+        )
+        .route(
+            "/metadata",
+            post({
+                let module_db = Arc::clone(&module_db);
+                move |body| set_metadata(body, module_db)
+            }),
+        )
+        .route(
+            "/schedule",
+            post({
+                let module_db = Arc::clone(&module_db);
+                move |info| set_schedule(info, module_db)
+            }),
+        )
+        .route(
+            "/versions",
+            post({
+                let module_db = Arc::clone(&module_db);
+                move |info| versions(info, module_db)
+            }),
+        )
+        .route(
+            "/promote",
+            post({
+                let module_db = Arc::clone(&module_db);
+                move |info| promote(info, module_db)
+            }),
+        )
+        .route(
+            "/rollback",
+            post({
+                let module_db = Arc::clone(&module_db);
+                move |info| rollback(info, module_db)
+            }),
+        )
+        .layer(DefaultBodyLimit::max(body_limit))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header, Request},
+    };
+    use http_body_util::BodyExt;
+    use serde_json::json;
+    use tower::ServiceExt;
+    use xxfunc_db::MemoryStore;
+
+    use super::*;
+
+    const MINIMAL_MODULE: &[u8] = include_bytes!("../../examples/minimal/wasm_output/output.wasm");
+    const BOUNDARY: &str = "xxfunc-boundary";
+
+    fn test_router(db: Arc<MemoryStore>) -> Router {
+        router(db, Arc::new(ModuleRunner::new().unwrap()), 1 << 20)
+    }
+
+    // status and body of the response to the request
+    async fn send(router: Router, request: Request<Body>) -> (StatusCode, String) {
+        let response = router.oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, String::from_utf8_lossy(&body).into_owned())
+    }
+
+    async fn post_json(router: Router, uri: &str, body: serde_json::Value) -> (StatusCode, String) {
+        let request = Request::post(uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        send(router, request).await
+    }
+
+    async fn deploy_binary(router: Router, binary: &[u8]) -> (StatusCode, String) {
+        let mut body = format!(
+            "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"module\"; \
+             filename=\"module\"\r\nContent-Type: application/wasm\r\n\r\n"
+        )
+        .into_bytes();
+        body.extend_from_slice(binary);
+        body.extend_from_slice(format!("\r\n--{BOUNDARY}--\r\n").as_bytes());
+        let request = Request::post("/deploy")
+            .header(header::CONTENT_TYPE, format!("multipart/form-data; boundary={BOUNDARY}"))
+            .body(Body::from(body))
+            .unwrap();
+        send(router, request).await
+    }
+
+    #[tokio::test]
+    async fn test_deploy_rejects_invalid_module() {
+        let db = Arc::new(MemoryStore::new());
+        let router = test_router(db.clone());
+
+        let (status, body) = deploy_binary(router.clone(), b"not a wasm module").await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{body}");
+        assert_eq!(db.get_id("module").unwrap(), None);
+
+        let (status, body) = deploy_binary(router, MINIMAL_MODULE).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(db.list_versions("module").unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_version_routes_not_found() {
+        let db = Arc::new(MemoryStore::new());
+        db.deploy("module", MINIMAL_MODULE, None, Some(ABI_VERSION)).unwrap();
+        let router = test_router(db);
+
+        for (uri, body) in [
+            ("/versions", json!({ "module": "missing" })),
+            ("/promote", json!({ "module": "missing", "version": 1 })),
+            ("/promote", json!({ "module": "module", "version": 2 })),
+            ("/rollback", json!({ "module": "missing" })),
+        ] {
+            let (status, _) = post_json(router.clone(), uri, body.clone()).await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{uri} {body}");
+        }
+
+        // the first version has nothing to roll back to
+        let (status, _) = post_json(router, "/rollback", json!({ "module": "module" })).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn test_metadata() {
+        let db = Arc::new(MemoryStore::new());
+        db.deploy("module", MINIMAL_MODULE, Some("alice"), Some(ABI_VERSION)).unwrap();
+        let router = test_router(db.clone());
+
+        let update = json!({
+            "module": "module",
+            "description": "indexes transfers",
+            "tags": ["erc20"],
+            "timeout_ms": 500,
+        });
+        let (status, _) = post_json(router.clone(), "/metadata", update).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // fields left out are kept, null ones cleared
+        let update = json!({ "module": "module", "description": null, "fuel": 1000 });
+        let (status, _) = post_json(router.clone(), "/metadata", update).await;
+        assert_eq!(status, StatusCode::OK);
+        let metadata = db.get_info("module").unwrap().unwrap().metadata;
+        assert_eq!(
+            metadata,
+            ModuleMetadata {
+                owner: Some("alice".to_string()),
+                description: None,
+                filters: Vec::new(),
+                limits: xxfunc_db::ResourceLimits {
+                    fuel: Some(1000),
+                    memory: None,
+                    timeout: Some(Duration::from_millis(500)),
+                },
+                tags: vec!["erc20".to_string()],
+            }
+        );
+
+        let (status, _) =
+            post_json(router, "/metadata", json!({ "module": "missing", "owner": "bob" })).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+}
@@ -1,105 +1,43 @@
-use axum::{
-    extract::{DefaultBodyLimit, Json, Multipart},
-    http::StatusCode,
-    routing::post,
-    Router,
-};
+use clap::Parser;
 use eyre::Result;
-use serde::Deserialize;
 use std::sync::Arc;
-use tracing::{error, info};
-use xxfunc_db::{ModuleDatabase, ModuleState};
-
-async fn deploy(
-    mut multipart: Multipart,
-    module_db: Arc<ModuleDatabase>,
-) -> Result<String, StatusCode> {
-    let mut file_name = String::new();
-
-    while let Some(field) = multipart.next_field().await.map_err(|_| StatusCode::BAD_REQUEST)? {
-        let name = field.name().unwrap_or_default().to_string();
-        if name == "module" {
-            file_name = field.file_name().map(|f| f.to_string()).unwrap_or_default();
-            info!("Received file: {}", file_name);
-            let raw_data = field.bytes().await.map_err(|_| StatusCode::BAD_REQUEST)?;
-            module_db
-                .insert(&file_name, &raw_data)
-                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-            info!("Module '{}' inserted into database", file_name);
-        }
-    }
-
-    if file_name.is_empty() {
-        return Err(StatusCode::BAD_REQUEST);
-    }
-
-    Ok(file_name)
-}
-
-#[derive(Deserialize)]
-struct ModuleInfo {
-    module: String,
-}
-
-async fn start(
-    Json(info): Json<ModuleInfo>,
-    module_db: Arc<ModuleDatabase>,
-) -> Result<String, StatusCode> {
-    info!("Starting module: {}", info.module);
-    module_db.set_state(&info.module, ModuleState::Started).map_err(|e| {
-        error!("Failed to set module state: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
-    info!("Module '{}' started successfully", info.module);
-    Ok(info.module)
-}
-
-async fn stop(
-    Json(info): Json<ModuleInfo>,
-    module_db: Arc<ModuleDatabase>,
-) -> Result<String, StatusCode> {
-    info!("Stopping module: {}", info.module);
-    module_db.set_state(&info.module, ModuleState::Stopped).map_err(|e| {
-        error!("Failed to set module state: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
-    info!("Module '{}' stopped successfully", info.module);
-    Ok(info.module)
-}
+use tracing::info;
+use tracing_subscriber::EnvFilter;
+use xxfunc_db::ModuleDatabase;
+use xxfunc_runtime::wasm::ModuleRunner;
+use xxfunc_server::{
+    config::{Args, ServerConfig},
+    router, tls,
+};
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // initialize tracing
-    tracing_subscriber::fmt::init();
-    let module_db = Arc::new(ModuleDatabase::open("module.db")?);
-    info!("Module database initialized at module.db");
-
-    let app = Router::new()
-        .route(
-            "/deploy",
-            post({
-                let module_db = Arc::clone(&module_db);
-                move |multipart| deploy(multipart, module_db)
-            }),
-        )
-        .route(
-            "/start",
-            post({
-                let module_db = Arc::clone(&module_db);
-                move |info| start(info, module_db)
-            }),
-        )
-        .route(
-            "/stop",
-            post({
-                let module_db = Arc::clone(&module_db);
-                move |info| stop(info, module_db)
-            }),
-        )
-        .layer(DefaultBodyLimit::disable());
-
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    axum::serve(listener, app).await.unwrap();
-    info!("Server started on port 3000");
+    let config = ServerConfig::load(Args::parse())?;
+
+    // initialize tracing, the configured level taking precedence over `RUST_LOG`
+    let filter = match &config.log_level {
+        Some(level) => EnvFilter::try_new(level)?,
+        None => EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+    };
+    tracing_subscriber::fmt().with_env_filter(filter).init();
+
+    let module_db = Arc::new(ModuleDatabase::open(&config.db)?);
+    info!("Module database initialized at {}", config.db.display());
+    let runner = Arc::new(ModuleRunner::new()?);
+
+    let app = router(module_db, runner, config.body_limit);
+
+    let listener = tokio::net::TcpListener::bind(config.addr()).await?;
+    match &config.tls {
+        Some(tls_config) => {
+            let acceptor = tls::acceptor(tls_config)?;
+            info!("Server started on https://{}", config.addr());
+            tls::serve(listener, app, acceptor).await?;
+        }
+        None => {
+            info!("Server started on http://{}", config.addr());
+            axum::serve(listener, app).await?;
+        }
+    }
     Ok(())
 }
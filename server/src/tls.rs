@@ -0,0 +1,98 @@
+//! HTTPS, terminating TLS in front of the router.
+
+use std::{fs::File, io::BufReader, path::Path, sync::Arc, time::Duration};
+
+use axum::Router;
+use eyre::{eyre, Result, WrapErr};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto,
+    service::TowerToHyperService,
+};
+use rustls_pemfile::Item;
+use tokio::net::TcpListener;
+use tokio_rustls::{
+    rustls::{
+        pki_types::{CertificateDer, PrivateKeyDer},
+        ServerConfig,
+    },
+    TlsAcceptor,
+};
+use tracing::warn;
+
+use crate::config::TlsConfig;
+
+/// How long to wait before accepting connections again after failing to, e.g. when out of file
+/// descriptors.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Loads the certificate and key, failing early on invalid files.
+pub fn acceptor(config: &TlsConfig) -> Result<TlsAcceptor> {
+    Ok(TlsAcceptor::from(Arc::new(server_config(config)?)))
+}
+
+/// Serves the router over TLS on every connection accepted by the listener.
+pub async fn serve(listener: TcpListener, app: Router, acceptor: TlsAcceptor) -> Result<()> {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(err) => {
+                warn!(%err, "Failed to accept connection.");
+                tokio::time::sleep(ACCEPT_BACKOFF).await;
+                continue;
+            }
+        };
+        let acceptor = acceptor.clone();
+        let service = TowerToHyperService::new(app.clone());
+
+        tokio::spawn(async move {
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(err) => {
+                    warn!(%peer, %err, "TLS handshake failed.");
+                    return;
+                }
+            };
+            if let Err(err) = auto::Builder::new(TokioExecutor::new())
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                warn!(%peer, %err, "Connection failed.");
+            }
+        });
+    }
+}
+
+fn server_config(config: &TlsConfig) -> Result<ServerConfig> {
+    let certs = read_pem(&config.cert)?
+        .into_iter()
+        .filter_map(|item| match item {
+            Item::X509Certificate(der) => Some(CertificateDer::from(der)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    if certs.is_empty() {
+        return Err(eyre!("no certificate in {}", config.cert.display()));
+    }
+
+    let key = read_pem(&config.key)?
+        .into_iter()
+        .find_map(|item| match item {
+            Item::PKCS8Key(der) => Some(PrivateKeyDer::Pkcs8(der.into())),
+            Item::RSAKey(der) => Some(PrivateKeyDer::Pkcs1(der.into())),
+            Item::ECKey(der) => Some(PrivateKeyDer::Sec1(der.into())),
+            _ => None,
+        })
+        .ok_or_else(|| eyre!("no private key in {}", config.key.display()))?;
+
+    let mut server_config =
+        ServerConfig::builder().with_no_client_auth().with_single_cert(certs, key)?;
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(server_config)
+}
+
+fn read_pem(path: &Path) -> Result<Vec<Item>> {
+    let file = File::open(path).wrap_err_with(|| format!("failed to open {}", path.display()))?;
+    rustls_pemfile::read_all(&mut BufReader::new(file))
+        .wrap_err_with(|| format!("invalid PEM file {}", path.display()))
+}
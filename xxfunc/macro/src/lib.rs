@@ -1,11 +1,12 @@
 mod xxfunc_proc;
 
 use proc_macro::TokenStream;
-use syn::{parse_macro_input, ItemFn};
+use syn::{parse_macro_input, AttributeArgs, ItemFn};
 use xxfunc_proc::xxfunc_proc;
 
 #[proc_macro_attribute]
-pub fn main(_attr: TokenStream, input: TokenStream) -> TokenStream {
+pub fn main(attr: TokenStream, input: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(attr as AttributeArgs);
     let input = parse_macro_input!(input as ItemFn);
-    xxfunc_proc(input).into()
+    xxfunc_proc(args, input).into()
 }
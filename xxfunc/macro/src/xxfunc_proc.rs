@@ -1,8 +1,12 @@
 use proc_macro2::TokenStream;
 use quote::quote;
-use syn::{Error, ItemFn};
+use syn::{AttributeArgs, Error, ItemFn, Meta, NestedMeta, ReturnType, Type};
 
-pub fn xxfunc_proc(input_fn: ItemFn) -> TokenStream {
+/// Version of the guest ABI generated by the macro, checked by the runtime when a module is
+/// deployed. Must be bumped with any change to the exports or imports below.
+const ABI_VERSION: u32 = 1;
+
+pub fn xxfunc_proc(args: AttributeArgs, input_fn: ItemFn) -> TokenStream {
     if input_fn.sig.ident != "main" {
         return Error::new_spanned(
             &input_fn.sig.ident,
@@ -11,6 +15,20 @@ pub fn xxfunc_proc(input_fn: ItemFn) -> TokenStream {
         .to_compile_error();
     }
 
+    let mut stateful = false;
+    for arg in args {
+        match arg {
+            NestedMeta::Meta(Meta::Path(path)) if path.is_ident("stateful") => stateful = true,
+            arg => {
+                return Error::new_spanned(
+                    arg,
+                    "Unknown xxfunc::main argument, expected `stateful`",
+                )
+                .to_compile_error()
+            }
+        }
+    }
+
     let fn_body = &input_fn.block;
     let return_type = &input_fn.sig.output;
     let is_async = input_fn.sig.asyncness.is_some();
@@ -41,39 +59,102 @@ pub fn xxfunc_proc(input_fn: ItemFn) -> TokenStream {
         quote! { __xxfunc_inner(data) }
     };
 
+    // a handler returning `Result` reports its error to the host, any other return value is
+    // treated as success
+    let result_handling = if returns_result(return_type) {
+        quote! {
+            match result {
+                Ok(_) => 0,
+                Err(err) => {
+                    // copy the error message into a fresh allocation and pack its location
+                    let message = err.to_string();
+                    let len = message.len() as u64;
+                    let ptr = alloc(len);
+                    let buffer =
+                        unsafe { slice::from_raw_parts_mut(ptr as *mut u8, message.len()) };
+                    buffer.copy_from_slice(message.as_bytes());
+                    (ptr << 32) | len
+                }
+            }
+        }
+    } else {
+        quote! {
+            let _ = result;
+            0
+        }
+    };
+
+    // the runtime keeps a stateful module's instance alive across notifications
+    let stateful_marker = if stateful {
+        quote! {
+            #[no_mangle]
+            pub extern "C" fn xxfunc_stateful() {}
+        }
+    } else {
+        quote! {}
+    };
+
     let expanded = quote! {
         use core::slice;
-        use std::sync::Mutex;
+        use std::{alloc::Layout, sync::Once};
+
+        static PANIC_HOOK: Once = Once::new();
+
+        #[link(wasm_import_module = "xxfunc")]
+        extern "C" {
+            // hands the panic message to the host before the guest aborts
+            #[link_name = "panic"]
+            fn __xxfunc_panic(message_ptr: u64, message_size: u64);
+        }
 
-        static LATEST_ALLOCATION: Mutex<Option<(u64, u64)>> = Mutex::new(None);
+        // layout shared by `alloc` and `dealloc`, zero sized buffers still get a unique pointer
+        fn __xxfunc_layout(data_size: u64) -> Layout {
+            let size = (data_size as usize).max(1);
+            Layout::from_size_align(size, 1).expect("invalid allocation size")
+        }
 
+        /// Allocates a buffer of `data_size` bytes owned by the host until it is passed back to
+        /// `dealloc`. Returns `0` if the allocation failed.
         #[no_mangle]
         pub extern "C" fn alloc(data_size: u64) -> u64 {
-            let mut buf = Vec::with_capacity(data_size as usize);
-            let ptr = buf.as_mut_ptr();
-            std::mem::forget(buf);
-            let data_ptr = ptr as *const u8 as u64;
+            unsafe { std::alloc::alloc(__xxfunc_layout(data_size)) as u64 }
+        }
 
-            *LATEST_ALLOCATION.lock().expect("failed to acquire mutex") = Some((ptr as u64, data_size));
+        /// Version of the guest ABI this module was built against.
+        #[no_mangle]
+        pub extern "C" fn xxfunc_abi_version() -> u32 {
+            #ABI_VERSION
+        }
 
-            data_ptr
+        /// Frees a buffer previously returned by `alloc` with the same `data_size`.
+        #[no_mangle]
+        pub extern "C" fn dealloc(data_ptr: u64, data_size: u64) {
+            unsafe { std::alloc::dealloc(data_ptr as *mut u8, __xxfunc_layout(data_size)) }
         }
 
+        /// Returns `0` on success. Otherwise the error message was written to a buffer from
+        /// `alloc`, which the host frees, and its location is returned as `(ptr << 32) | len`.
         #[no_mangle]
         pub extern "C" fn process(data_ptr: u64, data_size: u64) -> u64 {
-            assert_eq!(
-                (data_ptr, data_size),
-                LATEST_ALLOCATION.lock().expect("failed to acquire mutex").expect("no last allocation")
-            );
+            PANIC_HOOK.call_once(|| {
+                let default_hook = std::panic::take_hook();
+                std::panic::set_hook(Box::new(move |info| {
+                    // "panicked at <location>:\n<message>"
+                    let message = info.to_string();
+                    unsafe { __xxfunc_panic(message.as_ptr() as u64, message.len() as u64) };
+                    default_hook(info);
+                }));
+            });
 
             let data = unsafe { slice::from_raw_parts(data_ptr as *const u8, data_size as usize) };
 
-            #runtime_creation;
+            let result = { #runtime_creation };
 
-            let notification = String::from_utf8_lossy(data);
-            notification.len() as u64
+            #result_handling
         }
 
+        #stateful_marker
+
         #inner_fn
 
         fn main() {}
@@ -81,3 +162,16 @@ pub fn xxfunc_proc(input_fn: ItemFn) -> TokenStream {
 
     expanded
 }
+
+// whether the handler is declared as returning a `Result`
+fn returns_result(return_type: &ReturnType) -> bool {
+    match return_type {
+        ReturnType::Type(_, ty) => match ty.as_ref() {
+            Type::Path(path) => {
+                path.path.segments.last().is_some_and(|segment| segment.ident == "Result")
+            }
+            _ => false,
+        },
+        ReturnType::Default => false,
+    }
+}